@@ -1,118 +1,70 @@
-use anyhow::{Ok, Result};
+use chrono::{DateTime, Local};
 use std::io::{Read, Write};
+use std::time::Duration;
 
+pub mod framing;
+pub mod process;
+#[cfg(unix)]
+pub mod pty;
 pub mod tcp;
+pub mod udp;
+#[cfg(unix)]
+pub mod unix;
 pub mod usb;
 
+// Short enough that the runner never sits on a read while it has data waiting to be sent
+pub const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Framing happens in the runner, connections only move bytes
 pub trait Communicate: Read + Write {
-    fn read_until(&mut self, buf: &mut [u8], until: u8) -> Result<usize> {
-        let mut bytes_read = 0;
-        for current_byte in buf.iter_mut() {
-            let mut byte: [u8; 1] = [0; 1];
-            let result = self.read(&mut byte)?;
-            if result == 0 {
-                break;
-            }
-            bytes_read += 1;
-            *current_byte = byte[0];
-            if *current_byte == until {
-                break;
-            }
-        }
+    // Out of band output, like a spawned program's stderr, that belongs in the transcript
+    fn drain_stderr(&mut self) -> Vec<(DateTime<Local>, Vec<u8>)> {
+        Vec::new()
+    }
 
-        Ok(bytes_read)
+    // An empty datagram is a real message, so a 0 byte read only means the other side left on streams
+    fn is_datagram(&self) -> bool {
+        false
     }
 }
 
+// Reads on every connection time out quickly, so tests keep at it until what they want shows up
 #[cfg(test)]
-mod tests {
-
-    use super::*;
-    use pretty_assertions::assert_eq;
-    use std::fs::File;
-    use std::io::{Error, Read, Seek, Write};
-    use tempfile::tempfile;
-
-    struct FailedReader;
-    impl Read for FailedReader {
-        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
-            Err(Error::other("Simulated read failure"))
+pub mod test_support {
+    use std::io::{ErrorKind, Read};
+    use std::time::{Duration, Instant};
+
+    use super::framing::{Delimiter, Framer};
+
+    fn read_retrying(connection: &mut impl Read, buf: &mut [u8], start: Instant) -> usize {
+        loop {
+            match connection.read(buf) {
+                Ok(bytes_read) => return bytes_read,
+                Err(error)
+                    if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+                        && start.elapsed() < Duration::from_secs(5) => {}
+                Err(error) => panic!("Failed to read from connection: {error}"),
+            }
         }
     }
 
-    impl Write for FailedReader {
-        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
-            Err(Error::other("Simulated write failure"))
-        }
-        fn flush(&mut self) -> std::io::Result<()> {
-            Err(Error::other("Simulated flush failure"))
+    pub fn read_fully(connection: &mut impl Read, buf: &mut [u8]) {
+        let start = Instant::now();
+        let mut filled = 0;
+        while filled < buf.len() {
+            filled += read_retrying(connection, &mut buf[filled..], start);
         }
     }
 
-    impl Communicate for FailedReader {}
-    impl Communicate for File {}
-
-    #[test]
-    fn read_until_fail_failed_read() {
-        let mut failed_reader = FailedReader;
-        let mut buf: [u8; 10] = [0; 10];
-
-        let result = failed_reader.read_until(&mut buf, b'\n');
-
-        assert!(result.is_err(), "Somehow this forced failed read passed")
-    }
-
-    #[test]
-    fn read_until_pass_byte_found() {
-        let mut temp_file = tempfile().expect("Failed to create tempfile");
-        let mut buf: [u8; 16] = [0; 16];
-        writeln!(temp_file, "Hello World!").expect("Failed to write to tempfile");
-        temp_file.rewind().expect("Failed to rewind tempfile");
-
-        let bytes_read = temp_file
-            .read_until(&mut buf, b'!')
-            .expect("Somehow the temp file read failed");
-
-        assert_eq!(bytes_read, String::from("Hello World!").len());
-        assert_eq!(
-            "Hello World!",
-            str::from_utf8(&buf[..bytes_read]).expect("Failed to convert bytes to str")
-        );
-    }
-
-    #[test]
-    fn read_until_pass_byte_not_found() {
-        let mut temp_file = tempfile().expect("Failed to create tempfile");
-        let mut buf: [u8; 16] = [0; 16];
-        writeln!(temp_file, "Hello World!").expect("Failed to write to tempfile");
-        temp_file.rewind().expect("Failed to rewind tempfile");
-
-        let bytes_read = temp_file
-            .read_until(&mut buf, b'4')
-            .expect("Somehow the temp file read failed");
-
-        assert_eq!(bytes_read, String::from("Hello World!\n").len());
-        assert_eq!(
-            "Hello World!\n",
-            str::from_utf8(&buf[..bytes_read]).expect("Failed to convert bytes to str")
-        );
-    }
-
-    #[test]
-    fn read_until_pass_buffer_full() {
-        let mut temp_file = tempfile().expect("Failed to create tempfile");
-        let mut buf: [u8; 5] = [0; 5];
-        writeln!(temp_file, "Hello World!").expect("Failed to write to tempfile");
-        temp_file.rewind().expect("Failed to rewind tempfile");
-
-        let bytes_read = temp_file
-            .read_until(&mut buf, b'!')
-            .expect("Somehow the temp file read failed");
-
-        assert_eq!(bytes_read, String::from("Hello").len());
-        assert_eq!(
-            "Hello",
-            str::from_utf8(&buf[..bytes_read]).expect("Failed to convert bytes to str")
-        );
+    pub fn read_lines(connection: &mut impl Read, count: usize) -> Vec<Vec<u8>> {
+        let start = Instant::now();
+        let mut framer = Delimiter::new(Vec::from(b"\n"));
+        let mut lines = Vec::new();
+        let mut buf: [u8; 64] = [0; 64];
+        while lines.len() < count {
+            let bytes_read = read_retrying(connection, &mut buf, start);
+            lines.extend(framer.push(&buf[..bytes_read], Instant::now()));
+        }
+        lines
     }
 }
@@ -0,0 +1,790 @@
+use crc::{CRC_8_SMBUS, CRC_16_IBM_3740, CRC_32_ISO_HDLC, Crc};
+use log::warn;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+// Anything longer than this without a frame boundary is handed on as is rather than growing forever
+const MAX_BUFFERED: usize = 64 * 1024;
+
+// Turns whatever the connection hands over into the frames the handler matches against
+pub trait Framer {
+    fn push(&mut self, data: &[u8], now: Instant) -> Vec<Vec<u8>>;
+
+    // Called when a read came back with nothing, so time based framers can close out a frame
+    fn idle(&mut self, _now: Instant) -> Vec<Vec<u8>> {
+        Vec::new()
+    }
+
+    // Frames that were found but failed validation, kept out of the handler's way
+    fn drain_rejected(&mut self) -> Vec<Vec<u8>> {
+        Vec::new()
+    }
+
+    // Wraps an outgoing payload the same way the other side expects to receive it
+    fn encode(&self, data: &[u8]) -> Vec<u8> {
+        Vec::from(data)
+    }
+}
+
+fn take_oversized(buffer: &mut Vec<u8>, frames: &mut Vec<Vec<u8>>) {
+    if buffer.len() >= MAX_BUFFERED {
+        warn!(
+            "No frame boundary in {} bytes, passing them on as one frame",
+            buffer.len()
+        );
+        frames.push(std::mem::take(buffer));
+    }
+}
+
+pub struct Delimiter {
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+}
+
+impl Delimiter {
+    pub fn new(delimiter: Vec<u8>) -> Self {
+        Delimiter {
+            delimiter,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl Framer for Delimiter {
+    fn push(&mut self, data: &[u8], _now: Instant) -> Vec<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+        let mut frames = Vec::new();
+        while let Some(end) = self
+            .buffer
+            .windows(self.delimiter.len())
+            .position(|window| window == self.delimiter)
+        {
+            let frame: Vec<u8> = self.buffer.drain(..end + self.delimiter.len()).collect();
+            // Blank lines between frames don't carry anything worth matching against
+            if end > 0 {
+                frames.push(Vec::from(&frame[..end]));
+            }
+        }
+        take_oversized(&mut self.buffer, &mut frames);
+        frames
+    }
+}
+
+pub struct FixedLength {
+    length: usize,
+    buffer: Vec<u8>,
+}
+
+impl FixedLength {
+    pub fn new(length: usize) -> Self {
+        FixedLength {
+            length,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl Framer for FixedLength {
+    fn push(&mut self, data: &[u8], _now: Instant) -> Vec<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+        let mut frames = Vec::new();
+        while self.buffer.len() >= self.length {
+            frames.push(self.buffer.drain(..self.length).collect());
+        }
+        frames
+    }
+}
+
+pub struct IdleGap {
+    gap: Duration,
+    buffer: Vec<u8>,
+    last_received: Option<Instant>,
+}
+
+impl IdleGap {
+    pub fn new(gap: Duration) -> Self {
+        IdleGap {
+            gap,
+            buffer: Vec::new(),
+            last_received: None,
+        }
+    }
+}
+
+impl Framer for IdleGap {
+    fn push(&mut self, data: &[u8], now: Instant) -> Vec<Vec<u8>> {
+        let mut frames = self.idle(now);
+        self.buffer.extend_from_slice(data);
+        self.last_received = Some(now);
+        take_oversized(&mut self.buffer, &mut frames);
+        frames
+    }
+
+    fn idle(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        match self.last_received {
+            Some(last_received)
+                if !self.buffer.is_empty() && now.duration_since(last_received) >= self.gap =>
+            {
+                self.last_received = None;
+                vec![std::mem::take(&mut self.buffer)]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+// Every chunk the connection produces is a frame, which is exactly a datagram for UDP
+pub struct Raw;
+
+impl Framer for Raw {
+    fn push(&mut self, data: &[u8], _now: Instant) -> Vec<Vec<u8>> {
+        vec![Vec::from(data)]
+    }
+}
+
+// Splits off every packet ended by `end`, the encodings below both use a single byte for that
+fn split_packets(buffer: &mut Vec<u8>, end: u8, rejected: &mut Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    let mut packets = Vec::new();
+    while let Some(position) = buffer.iter().position(|byte| *byte == end) {
+        let packet: Vec<u8> = buffer.drain(..=position).collect();
+        if position > 0 {
+            packets.push(Vec::from(&packet[..position]));
+        }
+    }
+    if buffer.len() >= MAX_BUFFERED {
+        warn!("No packet end in {} bytes, rejecting them", buffer.len());
+        rejected.push(std::mem::take(buffer));
+    }
+    packets
+}
+
+const SLIP_END: u8 = 0xc0;
+const SLIP_ESC: u8 = 0xdb;
+const SLIP_ESC_END: u8 = 0xdc;
+const SLIP_ESC_ESC: u8 = 0xdd;
+
+fn slip_decode(packet: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(packet.len());
+    let mut bytes = packet.iter();
+    while let Some(byte) = bytes.next() {
+        decoded.push(match *byte {
+            SLIP_ESC => match bytes.next() {
+                Some(&SLIP_ESC_END) => SLIP_END,
+                Some(&SLIP_ESC_ESC) => SLIP_ESC,
+                _ => return None,
+            },
+            byte => byte,
+        });
+    }
+    Some(decoded)
+}
+
+fn slip_encode(data: &[u8]) -> Vec<u8> {
+    // Leading END flushes out any line noise the other side picked up before the packet
+    let mut encoded = vec![SLIP_END];
+    for byte in data {
+        match *byte {
+            SLIP_END => encoded.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC => encoded.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+            byte => encoded.push(byte),
+        }
+    }
+    encoded.push(SLIP_END);
+    encoded
+}
+
+// RFC 1055 packets ended by 0xC0
+pub struct Slip {
+    encode_sends: bool,
+    buffer: Vec<u8>,
+    rejected: Vec<Vec<u8>>,
+}
+
+impl Slip {
+    pub fn new(encode_sends: bool) -> Self {
+        Slip {
+            encode_sends,
+            buffer: Vec::new(),
+            rejected: Vec::new(),
+        }
+    }
+}
+
+impl Framer for Slip {
+    fn push(&mut self, data: &[u8], _now: Instant) -> Vec<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+        let mut frames = Vec::new();
+        for packet in split_packets(&mut self.buffer, SLIP_END, &mut self.rejected) {
+            match slip_decode(&packet) {
+                Some(frame) => frames.push(frame),
+                None => {
+                    warn!("Rejected a SLIP packet with a bad escape sequence");
+                    self.rejected.push(packet);
+                }
+            }
+        }
+        frames
+    }
+
+    fn drain_rejected(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.rejected)
+    }
+
+    fn encode(&self, data: &[u8]) -> Vec<u8> {
+        match self.encode_sends {
+            true => slip_encode(data),
+            false => Vec::from(data),
+        }
+    }
+}
+
+fn cobs_decode(packet: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(packet.len());
+    let mut index = 0;
+    while index < packet.len() {
+        let code = usize::from(packet[index]);
+        let block_end = index + code;
+        if code == 0 || block_end > packet.len() {
+            return None;
+        }
+        decoded.extend_from_slice(&packet[index + 1..block_end]);
+        index = block_end;
+        // A full block of 254 bytes doesn't stand in for a zero
+        if code < 0xff && index < packet.len() {
+            decoded.push(0);
+        }
+    }
+    Some(decoded)
+}
+
+fn cobs_encode(data: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![0];
+    let mut code_index = 0;
+    let mut code: u8 = 1;
+    for (index, byte) in data.iter().enumerate() {
+        if *byte != 0 {
+            encoded.push(*byte);
+            code += 1;
+        }
+        // A full block only opens another when there's more to come, otherwise it isn't canonical
+        if *byte == 0 || (code == 0xff && index + 1 < data.len()) {
+            encoded[code_index] = code;
+            code_index = encoded.len();
+            encoded.push(0);
+            code = 1;
+        }
+    }
+    encoded[code_index] = code;
+    encoded.push(0);
+    encoded
+}
+
+// Consistent overhead byte stuffing, packets ended by 0x00
+pub struct Cobs {
+    encode_sends: bool,
+    buffer: Vec<u8>,
+    rejected: Vec<Vec<u8>>,
+}
+
+impl Cobs {
+    pub fn new(encode_sends: bool) -> Self {
+        Cobs {
+            encode_sends,
+            buffer: Vec::new(),
+            rejected: Vec::new(),
+        }
+    }
+}
+
+impl Framer for Cobs {
+    fn push(&mut self, data: &[u8], _now: Instant) -> Vec<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+        let mut frames = Vec::new();
+        for packet in split_packets(&mut self.buffer, 0, &mut self.rejected) {
+            match cobs_decode(&packet) {
+                Some(frame) => frames.push(frame),
+                None => {
+                    warn!("Rejected a COBS packet with a bad block length");
+                    self.rejected.push(packet);
+                }
+            }
+        }
+        frames
+    }
+
+    fn drain_rejected(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.rejected)
+    }
+
+    fn encode(&self, data: &[u8]) -> Vec<u8> {
+        match self.encode_sends {
+            true => cobs_encode(data),
+            false => Vec::from(data),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    #[default]
+    Big,
+    Little,
+}
+
+impl Endian {
+    pub fn read(&self, bytes: &[u8]) -> u64 {
+        let fold = |value: u64, byte: &u8| (value << 8) | u64::from(*byte);
+        match self {
+            Endian::Big => bytes.iter().fold(0, fold),
+            Endian::Little => bytes.iter().rev().fold(0, fold),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Checksum {
+    Crc8,
+    Crc16Ccitt,
+    Crc32,
+}
+
+impl Checksum {
+    fn width(&self) -> usize {
+        match self {
+            Checksum::Crc8 => 1,
+            Checksum::Crc16Ccitt => 2,
+            Checksum::Crc32 => 4,
+        }
+    }
+
+    fn compute(&self, data: &[u8]) -> u64 {
+        match self {
+            Checksum::Crc8 => u64::from(Crc::<u8>::new(&CRC_8_SMBUS).checksum(data)),
+            Checksum::Crc16Ccitt => u64::from(Crc::<u16>::new(&CRC_16_IBM_3740).checksum(data)),
+            Checksum::Crc32 => u64::from(Crc::<u32>::new(&CRC_32_ISO_HDLC).checksum(data)),
+        }
+    }
+}
+
+// [sync][.. length ..][payload][checksum], the checksum covers everything in front of it
+#[derive(Debug, PartialEq, Clone)]
+pub struct Layout {
+    pub sync: Vec<u8>,
+    pub length_offset: usize,
+    pub length_width: usize,
+    pub endian: Endian,
+    pub length_includes_header: bool,
+    pub checksum: Option<Checksum>,
+}
+
+impl Layout {
+    fn header_length(&self) -> usize {
+        self.length_offset + self.length_width
+    }
+
+    fn checksum_width(&self) -> usize {
+        self.checksum.map_or(0, |checksum| checksum.width())
+    }
+}
+
+pub struct LengthPrefixed {
+    layout: Layout,
+    buffer: Vec<u8>,
+    rejected: Vec<Vec<u8>>,
+}
+
+impl LengthPrefixed {
+    pub fn new(layout: Layout) -> Self {
+        LengthPrefixed {
+            layout,
+            buffer: Vec::new(),
+            rejected: Vec::new(),
+        }
+    }
+
+    // Drops anything in front of the next sync word, returns false if there isn't one yet
+    fn align_to_sync(&mut self) -> bool {
+        let sync = &self.layout.sync;
+        if sync.is_empty() {
+            return true;
+        }
+        match self
+            .buffer
+            .windows(sync.len())
+            .position(|window| window == sync.as_slice())
+        {
+            Some(0) => true,
+            Some(start) => {
+                warn!("Dropped {start} bytes looking for a sync word");
+                self.buffer.drain(..start);
+                true
+            }
+            None => {
+                // The tail could still be the start of a sync word split across reads
+                let keep = sync.len() - 1;
+                let dropped = self.buffer.len().saturating_sub(keep);
+                if dropped > 0 {
+                    warn!("Dropped {dropped} bytes looking for a sync word");
+                    self.buffer.drain(..dropped);
+                }
+                false
+            }
+        }
+    }
+
+    fn payload_length(&self) -> Option<usize> {
+        let layout = &self.layout;
+        let length_field = &self.buffer[layout.length_offset..layout.header_length()];
+        let declared = usize::try_from(layout.endian.read(length_field)).ok()?;
+        let payload_length = match layout.length_includes_header {
+            true => declared.checked_sub(layout.header_length())?,
+            false => declared,
+        };
+        (layout.header_length() + payload_length + layout.checksum_width() <= MAX_BUFFERED)
+            .then_some(payload_length)
+    }
+}
+
+impl Framer for LengthPrefixed {
+    fn push(&mut self, data: &[u8], _now: Instant) -> Vec<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+        let header_length = self.layout.header_length();
+        let mut frames = Vec::new();
+        while self.align_to_sync() && self.buffer.len() >= header_length {
+            let Some(payload_length) = self.payload_length() else {
+                // A length that can't be right means we're not really at the start of a frame
+                warn!("Dropped a byte with an impossible frame length");
+                self.buffer.remove(0);
+                continue;
+            };
+            let checksum_offset = header_length + payload_length;
+            let frame_length = checksum_offset + self.layout.checksum_width();
+            if self.buffer.len() < frame_length {
+                break;
+            }
+
+            let frame: Vec<u8> = self.buffer.drain(..frame_length).collect();
+            if let Some(checksum) = self.layout.checksum {
+                let received = self.layout.endian.read(&frame[checksum_offset..]);
+                if checksum.compute(&frame[..checksum_offset]) != received {
+                    warn!("Rejected a {frame_length} byte frame with a bad checksum");
+                    self.rejected.push(frame);
+                    continue;
+                }
+            }
+            frames.push(Vec::from(&frame[header_length..checksum_offset]));
+        }
+        frames
+    }
+
+    fn drain_rejected(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.rejected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn delimiter_pass_crlf() {
+        let mut framer = Delimiter::new(Vec::from(b"\r\n"));
+        let now = Instant::now();
+
+        assert_eq!(framer.push(b"OK\r", now), Vec::<Vec<u8>>::new());
+        assert_eq!(
+            framer.push(b"\nREADY\r\n\r\npartial", now),
+            vec![Vec::from(b"OK"), Vec::from(b"READY")]
+        );
+        assert_eq!(framer.push(b"\r\n", now), vec![Vec::from(b"partial")]);
+    }
+
+    #[test]
+    fn delimiter_pass_prompt() {
+        let mut framer = Delimiter::new(Vec::from(b"> "));
+
+        assert_eq!(
+            framer.push(b"login> password> ", Instant::now()),
+            vec![Vec::from(b"login"), Vec::from(b"password")]
+        );
+    }
+
+    #[test]
+    fn delimiter_pass_long_line() {
+        let mut framer = Delimiter::new(Vec::from(b"\n"));
+        let line = vec![b'a'; 1000];
+        let now = Instant::now();
+
+        assert_eq!(framer.push(&line[..300], now), Vec::<Vec<u8>>::new());
+        assert_eq!(framer.push(&line[300..], now), Vec::<Vec<u8>>::new());
+        assert_eq!(framer.push(b"\n", now), vec![line]);
+    }
+
+    #[test]
+    fn delimiter_pass_oversized() {
+        let mut framer = Delimiter::new(Vec::from(b"\n"));
+
+        let frames = framer.push(&vec![0; MAX_BUFFERED], Instant::now());
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].len(), MAX_BUFFERED);
+    }
+
+    #[test]
+    fn fixed_length_pass() {
+        let mut framer = FixedLength::new(4);
+        let now = Instant::now();
+
+        assert_eq!(
+            framer.push(&[1, 2, 3, 4, 5, 6], now),
+            vec![vec![1, 2, 3, 4]]
+        );
+        assert_eq!(
+            framer.push(&[7, 8, 9, 10, 11, 12], now),
+            vec![vec![5, 6, 7, 8], vec![9, 10, 11, 12]]
+        );
+    }
+
+    #[test]
+    fn idle_gap_pass() {
+        let mut framer = IdleGap::new(Duration::from_millis(50));
+        let start = Instant::now();
+
+        assert_eq!(framer.push(b"AB", start), Vec::<Vec<u8>>::new());
+        assert_eq!(
+            framer.push(b"CD", start + Duration::from_millis(10)),
+            Vec::<Vec<u8>>::new()
+        );
+        assert_eq!(
+            framer.idle(start + Duration::from_millis(30)),
+            Vec::<Vec<u8>>::new()
+        );
+        assert_eq!(
+            framer.idle(start + Duration::from_millis(60)),
+            vec![Vec::from(b"ABCD")]
+        );
+        assert_eq!(
+            framer.idle(start + Duration::from_millis(200)),
+            Vec::<Vec<u8>>::new()
+        );
+    }
+
+    #[test]
+    fn idle_gap_pass_gap_between_reads() {
+        let mut framer = IdleGap::new(Duration::from_millis(50));
+        let start = Instant::now();
+
+        framer.push(b"first", start);
+
+        assert_eq!(
+            framer.push(b"second", start + Duration::from_millis(80)),
+            vec![Vec::from(b"first")]
+        );
+    }
+
+    #[test]
+    fn raw_pass() {
+        let mut framer = Raw;
+
+        assert_eq!(
+            framer.push(b"first\nline", Instant::now()),
+            vec![Vec::from(b"first\nline")]
+        );
+    }
+
+    fn sync_layout(checksum: Option<Checksum>) -> Layout {
+        Layout {
+            sync: vec![0xaa, 0x55],
+            length_offset: 2,
+            length_width: 1,
+            endian: Endian::Big,
+            length_includes_header: false,
+            checksum,
+        }
+    }
+
+    #[test]
+    fn endian_read_pass() {
+        assert_eq!(Endian::Big.read(&[0x12, 0x34]), 0x1234);
+        assert_eq!(Endian::Little.read(&[0x12, 0x34]), 0x3412);
+    }
+
+    #[test]
+    fn checksum_compute_pass() {
+        // Standard check values for the ASCII digits 1 through 9
+        assert_eq!(Checksum::Crc8.compute(b"123456789"), 0xf4);
+        assert_eq!(Checksum::Crc16Ccitt.compute(b"123456789"), 0x29b1);
+        assert_eq!(Checksum::Crc32.compute(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn length_prefixed_pass_split_reads() {
+        let mut framer = LengthPrefixed::new(sync_layout(None));
+        let now = Instant::now();
+
+        assert_eq!(framer.push(&[0x00, 0xaa], now), Vec::<Vec<u8>>::new());
+        assert_eq!(framer.push(&[0x55, 0x03, 0x01], now), Vec::<Vec<u8>>::new());
+        assert_eq!(
+            framer.push(&[0x02, 0x03, 0xaa, 0x55, 0x00], now),
+            vec![vec![0x01, 0x02, 0x03], vec![]]
+        );
+    }
+
+    #[test]
+    fn length_prefixed_pass_little_endian_including_header() {
+        let mut framer = LengthPrefixed::new(Layout {
+            sync: Vec::new(),
+            length_offset: 1,
+            length_width: 2,
+            endian: Endian::Little,
+            length_includes_header: true,
+            checksum: None,
+        });
+
+        assert_eq!(
+            framer.push(&[0x07, 0x05, 0x00, 0xde, 0xad], Instant::now()),
+            vec![vec![0xde, 0xad]]
+        );
+    }
+
+    #[test]
+    fn length_prefixed_pass_checksum() {
+        let mut framer = LengthPrefixed::new(sync_layout(Some(Checksum::Crc16Ccitt)));
+        let mut frame = vec![0xaa, 0x55, 0x02, 0x10, 0x20];
+        let checksum = Checksum::Crc16Ccitt.compute(&frame) as u16;
+        frame.extend_from_slice(&checksum.to_be_bytes());
+
+        assert_eq!(framer.push(&frame, Instant::now()), vec![vec![0x10, 0x20]]);
+        assert_eq!(framer.drain_rejected(), Vec::<Vec<u8>>::new());
+    }
+
+    #[test]
+    fn length_prefixed_fail_bad_checksum() {
+        let mut framer = LengthPrefixed::new(sync_layout(Some(Checksum::Crc8)));
+        let mut good = vec![0xaa, 0x55, 0x01, 0x42];
+        good.push(Checksum::Crc8.compute(&good) as u8);
+        let mut bad = good.clone();
+        bad[3] = 0x43;
+
+        let frames = framer.push(&[bad.clone(), good].concat(), Instant::now());
+
+        assert_eq!(frames, vec![vec![0x42]]);
+        assert_eq!(framer.drain_rejected(), vec![bad]);
+        assert_eq!(framer.drain_rejected(), Vec::<Vec<u8>>::new());
+    }
+
+    #[test]
+    fn length_prefixed_fail_impossible_length() {
+        let mut framer = LengthPrefixed::new(Layout {
+            length_includes_header: true,
+            ..sync_layout(None)
+        });
+
+        // A length shorter than the header itself can't be a real frame, so resync on the next one
+        assert_eq!(
+            framer.push(&[0xaa, 0x55, 0x01, 0xaa, 0x55, 0x04, 0x09], Instant::now()),
+            vec![vec![0x09]]
+        );
+    }
+
+    #[test]
+    fn slip_pass() {
+        let mut framer = Slip::new(false);
+        let now = Instant::now();
+
+        assert_eq!(
+            framer.push(&[0xc0, 0x01, 0xdb, 0xdc], now),
+            Vec::<Vec<u8>>::new()
+        );
+        assert_eq!(
+            framer.push(&[0xdb, 0xdd, 0x02, 0xc0, 0x03, 0xc0], now),
+            vec![vec![0x01, 0xc0, 0xdb, 0x02], vec![0x03]]
+        );
+        assert_eq!(framer.drain_rejected(), Vec::<Vec<u8>>::new());
+    }
+
+    #[test]
+    fn slip_fail_bad_escape() {
+        let mut framer = Slip::new(false);
+
+        let frames = framer.push(&[0x01, 0xdb, 0x01, 0xc0, 0x02, 0xc0], Instant::now());
+
+        assert_eq!(frames, vec![vec![0x02]]);
+        assert_eq!(framer.drain_rejected(), vec![vec![0x01, 0xdb, 0x01]]);
+    }
+
+    #[test]
+    fn slip_encode_pass() {
+        assert_eq!(
+            Slip::new(true).encode(&[0x01, 0xc0, 0xdb]),
+            vec![0xc0, 0x01, 0xdb, 0xdc, 0xdb, 0xdd, 0xc0]
+        );
+        assert_eq!(Slip::new(false).encode(&[0xc0]), vec![0xc0]);
+    }
+
+    #[test]
+    fn cobs_pass() {
+        let mut framer = Cobs::new(false);
+
+        assert_eq!(
+            framer.push(
+                &[0x01, 0x01, 0x00, 0x03, 0x11, 0x22, 0x02, 0x33, 0x00],
+                Instant::now()
+            ),
+            vec![vec![0x00], vec![0x11, 0x22, 0x00, 0x33]]
+        );
+    }
+
+    #[test]
+    fn cobs_fail_bad_block_length() {
+        let mut framer = Cobs::new(false);
+
+        let frames = framer.push(&[0x05, 0x11, 0x00, 0x02, 0x22, 0x00], Instant::now());
+
+        assert_eq!(frames, vec![vec![0x22]]);
+        assert_eq!(framer.drain_rejected(), vec![vec![0x05, 0x11]]);
+    }
+
+    #[test]
+    fn cobs_encode_round_trip_pass() {
+        let long_block: Vec<u8> = (1..=254).collect();
+        for payload in [
+            vec![],
+            vec![0x00],
+            vec![0x11, 0x22, 0x00, 0x33],
+            long_block.clone(),
+            [long_block, vec![0x00, 0x01]].concat(),
+        ] {
+            let encoded = Cobs::new(true).encode(&payload);
+            assert_eq!(encoded.iter().filter(|byte| **byte == 0).count(), 1);
+
+            let mut framer = Cobs::new(false);
+            let frames = framer.push(&encoded, Instant::now());
+
+            // Empty packets never make it out as frames
+            match payload.is_empty() {
+                true => assert_eq!(encoded, vec![0x01, 0x00]),
+                false => assert_eq!(frames, vec![payload]),
+            }
+        }
+    }
+
+    #[test]
+    fn cobs_encode_full_blocks_pass() {
+        let one_block: Vec<u8> = (1..=254).collect();
+        let two_blocks = [one_block.clone(), one_block.clone()].concat();
+
+        // One code byte per full block and the delimiter, no empty block tacked on the end
+        for (payload, length) in [(one_block, 256), (two_blocks, 511)] {
+            let encoded = Cobs::new(true).encode(&payload);
+            assert_eq!(encoded.len(), length);
+            assert_eq!(encoded[0], 0xff);
+
+            let mut framer = Cobs::new(false);
+            assert_eq!(framer.push(&encoded, Instant::now()), vec![payload]);
+        }
+    }
+}
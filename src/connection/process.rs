@@ -0,0 +1,279 @@
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Local};
+use crossbeam::channel::{self, Receiver, RecvTimeoutError};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::thread;
+
+use super::{Communicate, READ_TIMEOUT};
+
+const OUTPUT_CHUNK_SIZE: usize = 1024;
+
+// Pipes and terminals can't time out on their own, so a reader on the side hands data over
+pub(super) struct OutputPump {
+    chunks: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+impl OutputPump {
+    pub(super) fn spawn(mut output: impl Read + Send + 'static) -> Self {
+        let (chunk_tx, chunk_rx) = channel::unbounded();
+        thread::spawn(move || {
+            let mut buf = [0; OUTPUT_CHUNK_SIZE];
+            while let Ok(bytes_read @ 1..) = output.read(&mut buf) {
+                if chunk_tx.send(Vec::from(&buf[..bytes_read])).is_err() {
+                    break;
+                }
+            }
+        });
+        OutputPump {
+            chunks: chunk_rx,
+            pending: Vec::new(),
+        }
+    }
+
+    pub(super) fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            self.pending = match self.chunks.recv_timeout(READ_TIMEOUT) {
+                Ok(chunk) => chunk,
+                Err(RecvTimeoutError::Timeout) => return Err(ErrorKind::WouldBlock.into()),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(
+                        ErrorKind::BrokenPipe,
+                        "Process closed its output",
+                    ));
+                }
+            };
+        }
+
+        let bytes_read = buf.len().min(self.pending.len());
+        buf[..bytes_read].copy_from_slice(&self.pending[..bytes_read]);
+        self.pending.drain(..bytes_read);
+        Ok(bytes_read)
+    }
+}
+
+// Spawned programs shouldn't outlive the run, no matter how it ends
+pub(super) fn kill_and_reap(child: &mut Child) {
+    if let Err(msg) = child.kill() {
+        warn!("Failed to kill process {}: {msg}", child.id());
+    }
+    match child.wait() {
+        Ok(status) => info!("Process {} exited with {status}", child.id()),
+        Err(msg) => warn!("Failed to reap process {}: {msg}", child.id()),
+    }
+}
+
+pub struct Connection {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: OutputPump,
+    stderr: Receiver<(DateTime<Local>, Vec<u8>)>,
+}
+
+impl Connection {
+    pub fn new(
+        program: String,
+        args: Vec<String>,
+        env: HashMap<String, String>,
+        cwd: Option<String>,
+    ) -> Result<Self> {
+        let mut command = Command::new(&program);
+        command
+            .args(&args)
+            .envs(&env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(cwd) = cwd {
+            command.current_dir(cwd);
+        }
+
+        let mut child = command.spawn()?;
+        info!("Spawned {program} with pid {}", child.id());
+        let stdin = child.stdin.take().ok_or(anyhow!("Failed to open stdin"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or(anyhow!("Failed to open stdout"))?;
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or(anyhow!("Failed to open stderr"))?;
+
+        let (stderr_tx, stderr_rx) = channel::unbounded();
+        thread::spawn(move || {
+            let mut stderr = BufReader::new(stderr);
+            let mut line = Vec::new();
+            while let Ok(1..) = stderr.read_until(b'\n', &mut line) {
+                if line.ends_with(b"\n") {
+                    line.pop();
+                }
+                if stderr_tx.send((Local::now(), line.clone())).is_err() {
+                    break;
+                }
+                line.clear();
+            }
+        });
+
+        Ok(Connection {
+            child,
+            stdin,
+            stdout: OutputPump::spawn(stdout),
+            stderr: stderr_rx,
+        })
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        kill_and_reap(&mut self.child);
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdin.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin.flush()
+    }
+}
+
+impl Communicate for Connection {
+    fn drain_stderr(&mut self) -> Vec<(DateTime<Local>, Vec<u8>)> {
+        self.stderr.try_iter().collect()
+    }
+}
+
+#[cfg(unix)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::test_support::read_lines;
+    use pretty_assertions::assert_eq;
+    use std::time::{Duration, Instant};
+    use tempfile::tempdir;
+
+    fn spawn(program: &str, args: &[&str]) -> Connection {
+        Connection::new(
+            String::from(program),
+            args.iter().map(|arg| arg.to_string()).collect(),
+            HashMap::new(),
+            None,
+        )
+        .expect("Failed to spawn process")
+    }
+
+    #[test]
+    fn connection_new_missing_program_fail() {
+        let result = Connection::new(
+            String::from("program-that-does-not-exist"),
+            Vec::new(),
+            HashMap::new(),
+            None,
+        );
+
+        assert!(
+            result.is_err(),
+            "Somehow spawned a program that can't exist"
+        );
+    }
+
+    #[test]
+    fn connection_read_and_write_pass() {
+        let mut connection = spawn("cat", &[]);
+
+        connection
+            .write_all(b"Hello World!\n")
+            .expect("Failed to write to stdin");
+        connection.flush().expect("Failed to flush stdin");
+
+        assert_eq!(read_lines(&mut connection, 1)[0], b"Hello World!");
+    }
+
+    #[test]
+    fn connection_env_and_cwd_pass() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let mut connection = Connection::new(
+            String::from("sh"),
+            vec![String::from("-c"), String::from("echo $DEVICE_NAME; pwd")],
+            HashMap::from([(String::from("DEVICE_NAME"), String::from("simulator"))]),
+            Some(temp_dir.path().to_string_lossy().into_owned()),
+        )
+        .expect("Failed to spawn process");
+
+        let lines = read_lines(&mut connection, 2);
+        assert_eq!(lines[0], b"simulator");
+        let working_directory = &lines[1];
+        assert!(
+            working_directory.ends_with(
+                temp_dir
+                    .path()
+                    .file_name()
+                    .expect("Temp dir has no name")
+                    .as_encoded_bytes()
+            ),
+            "Process didn't run in the requested directory"
+        );
+    }
+
+    #[test]
+    fn connection_drain_stderr_pass() {
+        let mut connection = spawn("sh", &["-c", "echo oops 1>&2; echo done"]);
+
+        assert_eq!(read_lines(&mut connection, 1)[0], b"done");
+        let start = Instant::now();
+        let mut stderr = Vec::new();
+        while stderr.is_empty() && start.elapsed() < Duration::from_secs(5) {
+            stderr = connection.drain_stderr();
+        }
+
+        assert_eq!(stderr.len(), 1);
+        assert_eq!(stderr[0].1, b"oops");
+    }
+
+    #[test]
+    fn connection_read_after_exit_fail() {
+        let mut connection = spawn("true", &[]);
+        let start = Instant::now();
+        let mut buf: [u8; 8] = [0; 8];
+
+        let error = loop {
+            match connection.read(&mut buf) {
+                Err(msg) if msg.kind() == ErrorKind::WouldBlock => {
+                    assert!(
+                        start.elapsed() < Duration::from_secs(5),
+                        "Process never exited"
+                    )
+                }
+                Err(msg) => break msg,
+                Ok(_) => panic!("Somehow read output from a silent process"),
+            }
+        };
+        assert_eq!(error.kind(), ErrorKind::BrokenPipe);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn connection_drop_kills_process() {
+        let connection = spawn("sleep", &["30"]);
+        let pid = connection.child.id();
+
+        drop(connection);
+
+        assert!(
+            !std::path::Path::new(&format!("/proc/{pid}")).exists(),
+            "Process was still around after the connection was dropped"
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use anyhow::Result;
+use log::info;
+use nix::pty::{Winsize, openpty};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+
+use super::Communicate;
+use super::process::{OutputPump, kill_and_reap};
+
+const DEFAULT_ROWS: u16 = 24;
+const DEFAULT_COLS: u16 = 80;
+
+pub struct Connection {
+    child: Child,
+    master: File,
+    output: OutputPump,
+}
+
+impl Connection {
+    pub fn new(
+        program: String,
+        args: Vec<String>,
+        rows: Option<u16>,
+        cols: Option<u16>,
+    ) -> Result<Self> {
+        let window_size = Winsize {
+            ws_row: rows.unwrap_or(DEFAULT_ROWS),
+            ws_col: cols.unwrap_or(DEFAULT_COLS),
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let pty = openpty(Some(&window_size), None)?;
+
+        let mut command = Command::new(&program);
+        command
+            .args(&args)
+            .stdin(Stdio::from(pty.slave.try_clone()?))
+            .stdout(Stdio::from(pty.slave.try_clone()?))
+            .stderr(Stdio::from(pty.slave));
+        // The slave has to become the controlling terminal of a fresh session for job control and
+        // signals like ^C to behave the way they would in a real terminal
+        unsafe {
+            command.pre_exec(|| {
+                nix::unistd::setsid()?;
+                if nix::libc::ioctl(0, nix::libc::TIOCSCTTY as _, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        // Spawning drops our copies of the slave, so the master sees EOF once the program exits
+        let child = command.spawn()?;
+        info!(
+            "Spawned {program} on a pseudo-terminal with pid {}",
+            child.id()
+        );
+        let master = File::from(pty.master);
+        let output = OutputPump::spawn(master.try_clone()?);
+        Ok(Connection {
+            child,
+            master,
+            output,
+        })
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        kill_and_reap(&mut self.child);
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.output.read(buf)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.master.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.master.flush()
+    }
+}
+
+impl Communicate for Connection {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::test_support::read_lines;
+    use pretty_assertions::assert_eq;
+
+    fn spawn_shell(script: &str, rows: Option<u16>, cols: Option<u16>) -> Connection {
+        Connection::new(
+            String::from("sh"),
+            vec![String::from("-c"), String::from(script)],
+            rows,
+            cols,
+        )
+        .expect("Failed to spawn program on a pty")
+    }
+
+    #[test]
+    fn connection_new_missing_program_fail() {
+        let result = Connection::new(
+            String::from("program-that-does-not-exist"),
+            Vec::new(),
+            None,
+            None,
+        );
+
+        assert!(
+            result.is_err(),
+            "Somehow spawned a program that can't exist"
+        );
+    }
+
+    #[test]
+    fn connection_attached_to_tty_pass() {
+        let mut connection = spawn_shell("test -t 0 && test -t 1 && echo tty", None, None);
+
+        // Terminals translate newlines into CRLF on the way out
+        assert_eq!(read_lines(&mut connection, 1)[0], b"tty\r");
+    }
+
+    #[test]
+    fn connection_window_size_pass() {
+        let mut connection = spawn_shell("stty size", Some(40), Some(132));
+
+        assert_eq!(read_lines(&mut connection, 1)[0], b"40 132\r");
+    }
+
+    #[test]
+    fn connection_read_and_write_pass() {
+        let mut connection = spawn_shell(
+            "stty -echo; echo ready; read line; echo got $line",
+            None,
+            None,
+        );
+        assert_eq!(read_lines(&mut connection, 1)[0], b"ready\r");
+
+        connection
+            .write_all(b"hello\n")
+            .expect("Failed to write to the pty");
+        connection.flush().expect("Failed to flush the pty");
+
+        assert_eq!(read_lines(&mut connection, 1)[0], b"got hello\r");
+    }
+}
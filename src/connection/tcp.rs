@@ -1,31 +1,160 @@
-use anyhow::{Ok, Result};
-use std::io::{Read, Write};
-use std::net::TcpStream;
+use anyhow::{Result, bail};
+use log::{info, warn};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use super::Communicate;
+use super::{Communicate, READ_TIMEOUT};
 
-pub struct Connection(TcpStream);
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+struct Listener {
+    socket: TcpListener,
+    accept_timeout: Option<Duration>,
+    reaccept: bool,
+    disconnected_at: Option<Instant>,
+}
+
+impl Listener {
+    // Polls for a device until one connects or the accept timeout, counted from `since`, runs out
+    fn accept_until(&self, since: Instant, poll_for: Option<Duration>) -> io::Result<TcpStream> {
+        let poll_start = Instant::now();
+        loop {
+            match self.socket.accept() {
+                Ok((stream, peer)) => {
+                    info!("Device connected from {peer}");
+                    stream.set_nonblocking(false)?;
+                    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+                    return Ok(stream);
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+                Err(error) => return Err(error),
+            }
+
+            if self
+                .accept_timeout
+                .is_some_and(|timeout| since.elapsed() >= timeout)
+            {
+                return Err(io::Error::new(
+                    ErrorKind::NotConnected,
+                    "No device connected before the accept timeout",
+                ));
+            }
+            if poll_for.is_some_and(|duration| poll_start.elapsed() >= duration) {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            thread::sleep(ACCEPT_POLL_INTERVAL);
+        }
+    }
+}
+
+pub struct Connection {
+    stream: Option<TcpStream>,
+    listener: Option<Listener>,
+}
 
 impl Connection {
     pub fn new(address: String, port: u16) -> Result<Self> {
         let new_connection = TcpStream::connect(format!("{address}:{port}"))?;
-        Ok(Connection(new_connection))
+        new_connection.set_read_timeout(Some(READ_TIMEOUT))?;
+        Ok(Connection {
+            stream: Some(new_connection),
+            listener: None,
+        })
+    }
+
+    pub fn listen(
+        address: String,
+        port: u16,
+        accept_timeout: Option<Duration>,
+        reaccept: bool,
+    ) -> Result<Self> {
+        let socket = TcpListener::bind(format!("{address}:{port}"))?;
+        socket.set_nonblocking(true)?;
+        info!(
+            "Waiting for a device to connect on {}",
+            socket.local_addr()?
+        );
+
+        let listener = Listener {
+            socket,
+            accept_timeout,
+            reaccept,
+            disconnected_at: None,
+        };
+        let stream = match listener.accept_until(Instant::now(), None) {
+            Ok(stream) => stream,
+            Err(error) => bail!("Failed to accept a device connection: {error}"),
+        };
+        Ok(Connection {
+            stream: Some(stream),
+            listener: Some(listener),
+        })
+    }
+
+    // Only a listening connection that was asked to re-accept can recover from losing its device
+    fn handle_disconnect(&mut self) -> bool {
+        match self.listener.as_mut() {
+            Some(listener) if listener.reaccept => {
+                warn!("Device disconnected, waiting for it to connect again");
+                listener.disconnected_at = Some(Instant::now());
+                self.stream = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn reconnect(&mut self, poll_for: Option<Duration>) -> io::Result<&mut TcpStream> {
+        let Some(listener) = self.listener.as_mut() else {
+            return Err(ErrorKind::NotConnected.into());
+        };
+        let since = listener.disconnected_at.unwrap_or_else(Instant::now);
+        let stream = listener.accept_until(since, poll_for)?;
+        listener.disconnected_at = None;
+        Ok(self.stream.insert(stream))
     }
 }
 
 impl Read for Connection {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.0.read(buf)
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => self.reconnect(Some(READ_TIMEOUT))?,
+        };
+
+        match stream.read(buf) {
+            Ok(0) if !buf.is_empty() && self.handle_disconnect() => {
+                Err(ErrorKind::WouldBlock.into())
+            }
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted
+                ) && self.handle_disconnect() =>
+            {
+                Err(ErrorKind::WouldBlock.into())
+            }
+            result => result,
+        }
     }
 }
 
 impl Write for Connection {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.0.write(buf)
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => self.reconnect(Some(READ_TIMEOUT))?,
+        };
+        stream.write(buf)
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        self.0.flush()
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stream.as_mut() {
+            Some(stream) => stream.flush(),
+            None => Ok(()),
+        }
     }
 }
 
@@ -34,8 +163,8 @@ impl Communicate for Connection {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::connection::test_support::read_fully;
     use pretty_assertions::assert_eq;
-    use std::net::TcpListener;
     use std::thread;
 
     #[test]
@@ -69,7 +198,7 @@ mod tests {
             let mut client =
                 Connection::new(listener_ip, listener_port).expect("Failed to connect");
             let mut buf: [u8; 12] = [0; 12];
-            let _ = client.read(&mut buf).expect("Failed to read from client");
+            read_fully(&mut client, &mut buf);
             let _ = client.write(&buf).expect("Failed to write from client");
             client.flush().expect("Failed to flush data from client");
         });
@@ -84,4 +213,156 @@ mod tests {
         assert!(handle.join().is_ok(), "Thread joined with panic");
         assert_eq!(sent, received.as_slice());
     }
+
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .expect("Failed to reserve a port")
+            .local_addr()
+            .expect("Failed to get reserved port")
+            .port()
+    }
+
+    #[test]
+    fn connection_listen_accept_timeout_fail() {
+        let result = Connection::listen(
+            String::from("127.0.0.1"),
+            free_port(),
+            Some(Duration::from_millis(200)),
+            false,
+        );
+
+        assert!(result.is_err(), "Somehow a device connected");
+    }
+
+    #[test]
+    fn connection_listen_read_and_write_pass() {
+        let port = free_port();
+
+        // Need a thread to play the device dialing in
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            let mut device = loop {
+                match TcpStream::connect(("127.0.0.1", port)) {
+                    Ok(device) => break device,
+                    Err(_) if start.elapsed() < Duration::from_secs(5) => {
+                        thread::sleep(Duration::from_millis(10))
+                    }
+                    Err(error) => panic!("Device never connected: {error}"),
+                }
+            };
+            device.write_all(b"booted\n").expect("Failed device write");
+            let mut buf: [u8; 5] = [0; 5];
+            device.read_exact(&mut buf).expect("Failed device read");
+            buf
+        });
+
+        let mut connection = Connection::listen(
+            String::from("127.0.0.1"),
+            port,
+            Some(Duration::from_secs(5)),
+            false,
+        )
+        .expect("Failed to accept device");
+        let mut buf: [u8; 7] = [0; 7];
+        read_fully(&mut connection, &mut buf);
+        connection.write_all(b"hello").expect("Failed to write");
+
+        assert_eq!(b"booted\n", &buf);
+        assert_eq!(&handle.join().expect("Thread joined with panic"), b"hello");
+    }
+
+    #[test]
+    fn connection_listen_reaccept_pass() {
+        let port = free_port();
+
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            let first = loop {
+                match TcpStream::connect(("127.0.0.1", port)) {
+                    Ok(device) => break device,
+                    Err(_) if start.elapsed() < Duration::from_secs(5) => {
+                        thread::sleep(Duration::from_millis(10))
+                    }
+                    Err(error) => panic!("Device never connected: {error}"),
+                }
+            };
+            // Simulate a reboot by dropping the first connection and dialing in again
+            drop(first);
+            thread::sleep(Duration::from_millis(200));
+            let mut second = TcpStream::connect(("127.0.0.1", port)).expect("Failed to reconnect");
+            second
+                .write_all(b"rebooted\n")
+                .expect("Failed device write");
+            thread::sleep(Duration::from_millis(200));
+        });
+
+        let mut connection = Connection::listen(
+            String::from("127.0.0.1"),
+            port,
+            Some(Duration::from_secs(5)),
+            true,
+        )
+        .expect("Failed to accept device");
+        let mut buf: [u8; 9] = [0; 9];
+        read_fully(&mut connection, &mut buf);
+
+        assert_eq!(b"rebooted\n", &buf);
+        assert!(handle.join().is_ok(), "Thread joined with panic");
+    }
+
+    #[test]
+    fn connection_listen_write_while_disconnected_pass() {
+        let port = free_port();
+
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            loop {
+                match TcpStream::connect(("127.0.0.1", port)) {
+                    Ok(device) => break device,
+                    Err(_) if start.elapsed() < Duration::from_secs(5) => {
+                        thread::sleep(Duration::from_millis(10))
+                    }
+                    Err(error) => panic!("Device never connected: {error}"),
+                }
+            }
+        });
+
+        let mut connection = Connection::listen(String::from("127.0.0.1"), port, None, true)
+            .expect("Failed to accept device");
+        drop(handle.join().expect("Thread joined with panic"));
+        let start = Instant::now();
+        let mut buf: [u8; 4] = [0; 4];
+        while connection.stream.is_some() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "Disconnect never noticed"
+            );
+            let _ = connection.read(&mut buf);
+        }
+
+        // Without an accept timeout, a write has to give up instead of waiting for the device forever
+        let start = Instant::now();
+        let result = connection.write(b"hello");
+        assert_eq!(
+            result.map_err(|error| error.kind()),
+            Err(ErrorKind::WouldBlock)
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn connection_read_eof_without_reaccept_pass() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to open tcp listener");
+        let listener_addr = listener
+            .local_addr()
+            .expect("Failed to get test server port and address");
+        let mut client = Connection::new(listener_addr.ip().to_string(), listener_addr.port())
+            .expect("Failed to connect");
+        let (server, _) = listener.accept().expect("Failed to accept connection");
+        drop(server);
+
+        let mut buf: [u8; 4] = [0; 4];
+        let bytes_read = client.read(&mut buf).expect("Failed to read EOF");
+        assert_eq!(bytes_read, 0);
+    }
 }
@@ -0,0 +1,173 @@
+use anyhow::{Result, anyhow};
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+
+use super::{Communicate, READ_TIMEOUT};
+
+pub struct Connection(UdpSocket);
+
+impl Connection {
+    pub fn new(address: String, port: u16, local_port: Option<u16>) -> Result<Self> {
+        let remote = (address.as_str(), port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or(anyhow!("{address} did not resolve to any address"))?;
+        let local: SocketAddr = match remote {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, local_port.unwrap_or(0)).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, local_port.unwrap_or(0)).into(),
+        };
+
+        let new_connection = UdpSocket::bind(local)?;
+        new_connection.connect(remote)?;
+        new_connection.set_read_timeout(Some(READ_TIMEOUT))?;
+        Ok(Connection(new_connection))
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.recv(buf)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.send(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Communicate for Connection {
+    fn is_datagram(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn setup() -> (UdpSocket, Connection) {
+        let peer = UdpSocket::bind("127.0.0.1:0").expect("Failed to open udp peer");
+        let peer_addr = peer
+            .local_addr()
+            .expect("Failed to get test peer port and address");
+        let connection = Connection::new(peer_addr.ip().to_string(), peer_addr.port(), None)
+            .expect("Failed to open connection");
+        (peer, connection)
+    }
+
+    #[test]
+    fn connection_new_unresolvable_fail() {
+        assert!(
+            Connection::new(String::from("host.that.does.not.exist.invalid"), 9000, None).is_err(),
+            "Somehow resolved a host that can't exist"
+        );
+    }
+
+    #[test]
+    fn connection_new_local_port_pass() {
+        let peer = UdpSocket::bind("127.0.0.1:0").expect("Failed to open udp peer");
+        let peer_port = peer
+            .local_addr()
+            .expect("Failed to get peer address")
+            .port();
+        let reserved = UdpSocket::bind("127.0.0.1:0").expect("Failed to reserve a local port");
+        let local_port = reserved
+            .local_addr()
+            .expect("Failed to get local port")
+            .port();
+        drop(reserved);
+
+        let connection = Connection::new(String::from("127.0.0.1"), peer_port, Some(local_port))
+            .expect("Failed to open connection");
+
+        assert_eq!(
+            connection
+                .0
+                .local_addr()
+                .expect("Failed to get connection address")
+                .port(),
+            local_port
+        );
+    }
+
+    #[test]
+    fn connection_read_and_write_pass() {
+        let (peer, mut connection) = setup();
+
+        let sent = b"Hello World!";
+        connection.write_all(sent).expect("Failed connection write");
+        connection.flush().expect("Failed connection flush");
+        let mut received: [u8; 12] = [0; 12];
+        let (_, connection_addr) = peer.recv_from(&mut received).expect("Failed peer receive");
+        assert_eq!(sent, received.as_slice());
+
+        peer.send_to(b"Hello Back!!", connection_addr)
+            .expect("Failed peer send");
+        let bytes_read = connection
+            .read(&mut received)
+            .expect("Failed connection read");
+        assert_eq!(b"Hello Back!!", &received[..bytes_read]);
+    }
+
+    #[test]
+    fn connection_read_per_datagram_pass() {
+        let (peer, mut connection) = setup();
+        connection
+            .write_all(b"hi")
+            .expect("Failed connection write");
+        let mut buf: [u8; 32] = [0; 32];
+        let (_, connection_addr) = peer.recv_from(&mut buf).expect("Failed peer receive");
+
+        peer.send_to(b"first\nline", connection_addr)
+            .expect("Failed peer send");
+        peer.send_to(b"second", connection_addr)
+            .expect("Failed peer send");
+
+        let bytes_read = connection
+            .read(&mut buf)
+            .expect("Failed to read first datagram");
+        assert_eq!(b"first\nline", &buf[..bytes_read]);
+        let bytes_read = connection
+            .read(&mut buf)
+            .expect("Failed to read second datagram");
+        assert_eq!(b"second", &buf[..bytes_read]);
+    }
+
+    #[test]
+    fn connection_read_empty_datagram_pass() {
+        let (peer, mut connection) = setup();
+        connection
+            .write_all(b"hi")
+            .expect("Failed connection write");
+        let mut buf: [u8; 32] = [0; 32];
+        let (_, connection_addr) = peer.recv_from(&mut buf).expect("Failed peer receive");
+
+        peer.send_to(b"", connection_addr)
+            .expect("Failed peer send");
+
+        assert!(connection.is_datagram());
+        assert_eq!(
+            connection
+                .read(&mut buf)
+                .expect("Failed to read empty datagram"),
+            0
+        );
+    }
+
+    #[test]
+    fn connection_read_timeout_fail() {
+        let (_peer, mut connection) = setup();
+        let mut buf: [u8; 32] = [0; 32];
+
+        assert!(
+            connection.read(&mut buf).is_err(),
+            "Somehow read a datagram nobody sent"
+        );
+    }
+}
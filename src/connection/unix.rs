@@ -0,0 +1,97 @@
+use anyhow::Result;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+
+use super::{Communicate, READ_TIMEOUT};
+
+pub struct Connection(UnixStream);
+
+impl Connection {
+    pub fn new(path: String) -> Result<Self> {
+        let new_connection = UnixStream::connect(path)?;
+        new_connection.set_read_timeout(Some(READ_TIMEOUT))?;
+        Ok(Connection(new_connection))
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Communicate for Connection {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::test_support::read_fully;
+    use pretty_assertions::assert_eq;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+    use tempfile::tempdir;
+
+    #[test]
+    fn connection_new_fail_no_listener() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let socket_path = temp_dir.path().join("missing.sock");
+
+        assert!(
+            Connection::new(socket_path.to_string_lossy().into_owned()).is_err(),
+            "Somehow connected to a socket nobody is listening on"
+        );
+    }
+
+    #[test]
+    fn connection_new_pass() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let socket_path = temp_dir.path().join("device.sock");
+        let listener = UnixListener::bind(&socket_path).expect("Failed to open unix listener");
+        let socket_path = socket_path.to_string_lossy().into_owned();
+
+        // Need a thread to avoid blocking the thread the test runs in
+        let handle =
+            thread::spawn(move || Connection::new(socket_path).expect("Failed to connect"));
+        listener.accept().expect("Failed to accept connection");
+        assert!(handle.join().is_ok(), "Thread joined with panic");
+    }
+
+    #[test]
+    fn connection_read_and_write_pass() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let socket_path = temp_dir.path().join("device.sock");
+        let listener = UnixListener::bind(&socket_path).expect("Failed to open unix listener");
+        let socket_path = socket_path.to_string_lossy().into_owned();
+
+        // Need a thread to avoid blocking the thread the test runs in
+        let handle = thread::spawn(move || {
+            let mut client = Connection::new(socket_path).expect("Failed to connect");
+            let mut buf: [u8; 12] = [0; 12];
+            read_fully(&mut client, &mut buf);
+            let _ = client.write(&buf).expect("Failed to write from client");
+            client.flush().expect("Failed to flush data from client");
+        });
+        let (mut server, _) = listener.accept().expect("Failed to accept connection");
+
+        server
+            .write_all(b"Hello World!")
+            .expect("Failed server write");
+        let mut received: [u8; 12] = [0; 12];
+        server
+            .read_exact(&mut received)
+            .expect("Failed server read");
+
+        assert!(handle.join().is_ok(), "Thread joined with panic");
+        assert_eq!(b"Hello World!", received.as_slice());
+    }
+}
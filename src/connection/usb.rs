@@ -1,16 +1,42 @@
 use anyhow::{Ok, Result};
-use serialport::SerialPort;
+use serialport::{DataBits, FlowControl, Parity, SerialPort, StopBits};
 use std::io::{Read, Write};
 use std::time::Duration;
 
-use super::Communicate;
+use super::{Communicate, READ_TIMEOUT};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineSettings {
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    pub read_timeout: Duration,
+}
+
+// 8N1 with no flow control, which is what most devices ship with
+impl Default for LineSettings {
+    fn default() -> Self {
+        LineSettings {
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            read_timeout: READ_TIMEOUT,
+        }
+    }
+}
 
 pub struct Connection(Box<dyn SerialPort>);
 
 impl Connection {
-    pub fn new(port: String, baud_rate: u32) -> Result<Self> {
+    pub fn new(port: String, baud_rate: u32, settings: LineSettings) -> Result<Self> {
         let new_connection = serialport::new(port, baud_rate)
-            .timeout(Duration::from_secs(1))
+            .data_bits(settings.data_bits)
+            .parity(settings.parity)
+            .stop_bits(settings.stop_bits)
+            .flow_control(settings.flow_control)
+            .timeout(settings.read_timeout)
             .open()?;
         Ok(Connection(new_connection))
     }
@@ -50,7 +76,12 @@ mod tests {
     #[test]
     fn connection_new_port_open_fail() {
         assert!(
-            Connection::new("port/that/does/not/exist".to_string(), 115200).is_err(),
+            Connection::new(
+                "port/that/does/not/exist".to_string(),
+                115200,
+                LineSettings::default()
+            )
+            .is_err(),
             "There are major issues if this port actually exists"
         );
     }
@@ -62,7 +93,7 @@ mod tests {
         let master_pty = unsafe { PtyMaster::from_owned_fd(master_fd) };
         let dummy_port_path = ptsname_r(&master_pty).expect("Failed to get dummy port path");
         assert!(
-            Connection::new(dummy_port_path.clone(), 115200).is_ok(),
+            Connection::new(dummy_port_path.clone(), 115200, LineSettings::default()).is_ok(),
             "Failed to open the dummy port"
         );
     }
@@ -73,8 +104,8 @@ mod tests {
         let master_fd = dummy_port.master;
         let master_pty = unsafe { PtyMaster::from_owned_fd(master_fd) };
         let dummy_port_path = ptsname_r(&master_pty).expect("Failed to get dummy port path");
-        let mut new_connection =
-            Connection::new(dummy_port_path, 115200).expect("Failed to open dummy serial port");
+        let mut new_connection = Connection::new(dummy_port_path, 115200, LineSettings::default())
+            .expect("Failed to open dummy serial port");
 
         let message = b"Hello World!";
         write(master_pty.as_fd(), message).expect("Dummy port write failed");
@@ -86,14 +117,42 @@ mod tests {
         assert_eq!(buf.as_slice(), message);
     }
 
+    #[test]
+    fn connection_new_line_settings_pass() {
+        let dummy_port = setup();
+        let master_fd = dummy_port.master;
+        let master_pty = unsafe { PtyMaster::from_owned_fd(master_fd) };
+        let dummy_port_path = ptsname_r(&master_pty).expect("Failed to get dummy port path");
+        let settings = LineSettings {
+            data_bits: DataBits::Seven,
+            parity: Parity::Even,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            read_timeout: Duration::from_millis(250),
+        };
+
+        let new_connection = Connection::new(dummy_port_path, 9600, settings)
+            .expect("Failed to open dummy serial port");
+
+        // Ptys force 8N1 on their own, so only the parts they keep can be checked here
+        assert_eq!(
+            new_connection
+                .0
+                .stop_bits()
+                .expect("Failed to read stop bits"),
+            StopBits::One
+        );
+        assert_eq!(new_connection.0.timeout(), Duration::from_millis(250));
+    }
+
     #[test]
     fn connection_write_and_flush_pass() {
         let dummy_port = setup();
         let master_fd = dummy_port.master;
         let master_pty = unsafe { PtyMaster::from_owned_fd(master_fd) };
         let dummy_port_path = ptsname_r(&master_pty).expect("Failed to get dummy port path");
-        let mut new_connection =
-            Connection::new(dummy_port_path, 115200).expect("Failed to open dummy serial port");
+        let mut new_connection = Connection::new(dummy_port_path, 115200, LineSettings::default())
+            .expect("Failed to open dummy serial port");
 
         let message = b"Hello World!";
         let _ = new_connection
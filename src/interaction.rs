@@ -1,2 +1,7 @@
 pub mod command;
 pub mod config;
+pub mod duration;
+pub mod json;
+pub mod matcher;
+pub mod measurement;
+pub mod variables;
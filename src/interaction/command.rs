@@ -1,14 +1,245 @@
-use anyhow::{Result, bail};
-use hex;
-use serde::Deserialize;
-use std::time::Duration;
-use std::{fs::File, io::BufReader, path::PathBuf};
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, Instant};
+use std::{fs, path::PathBuf};
+
+use super::duration::RawDuration;
+use super::json::{JsonAssertion, RawJsonAssertion};
+use super::matcher::{Expectation, MaskedBytes, Pattern};
+use super::measurement::{Measurement, RawMeasurement};
+use super::variables::{self, Capture, CaptureSource, Definitions, Segment, Variables};
 
 #[derive(Deserialize)]
 #[serde(tag = "type")]
 enum RawSendable {
-    Hex { data: String },
-    Text { data: String },
+    Hex {
+        data: String,
+    },
+    Text {
+        data: String,
+    },
+    // What resolve turns the others into, never written in a scenario file
+    #[serde(skip)]
+    Resolved(Sendable),
+}
+
+impl RawSendable {
+    fn resolve(
+        self,
+        definitions: &Definitions,
+        captured: &HashSet<String>,
+    ) -> Result<(Sendable, Vec<String>)> {
+        Ok(match self {
+            RawSendable::Hex { data } => {
+                let (data, unresolved) =
+                    variables::parse_template(&data, definitions, captured, variables::decode_hex)?;
+                (Sendable::Hex { data }, unresolved)
+            }
+            RawSendable::Text { data } => {
+                let (data, unresolved) = variables::parse_template(
+                    &data,
+                    definitions,
+                    captured,
+                    variables::decode_text,
+                )?;
+                (Sendable::Text { data }, unresolved)
+            }
+            RawSendable::Resolved(sendable) => (sendable, Vec::new()),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum RawCapture {
+    Group { group: String },
+    Bytes { offset: usize, length: usize },
+}
+
+impl RawCapture {
+    fn into_capture(self, name: String, expect_regex: Option<&Pattern>) -> Result<Capture> {
+        let source = match self {
+            RawCapture::Group { group } => {
+                if !expect_regex.is_some_and(|pattern| pattern.has_group(&group)) {
+                    bail!("capture {name} needs an expect_regex with a group named {group}");
+                }
+                CaptureSource::Group(group)
+            }
+            RawCapture::Bytes { offset, length } => CaptureSource::Bytes { offset, length },
+        };
+        Ok(Capture { name, source })
+    }
+}
+
+// Expected bytes are text unless given as { "type": "Hex", ... } like a send
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawBytes {
+    Text(String),
+    Typed(RawTypedBytes),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", deny_unknown_fields)]
+enum RawTypedBytes {
+    Text {
+        data: String,
+    },
+    Hex {
+        data: String,
+        #[serde(default)]
+        mask: Option<String>,
+    },
+}
+
+impl RawBytes {
+    // Hex whitespace is ignored, so a hex value of only spaces is as empty as ""
+    fn is_empty(&self) -> bool {
+        match self {
+            RawBytes::Text(data) | RawBytes::Typed(RawTypedBytes::Text { data }) => data.is_empty(),
+            RawBytes::Typed(RawTypedBytes::Hex { data, .. }) => {
+                data.chars().all(char::is_whitespace)
+            }
+        }
+    }
+
+    fn strings_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            RawBytes::Text(data) | RawBytes::Typed(RawTypedBytes::Text { data }) => vec![data],
+            RawBytes::Typed(RawTypedBytes::Hex { data, mask }) => {
+                let mut strings = vec![data];
+                strings.extend(mask.as_mut());
+                strings
+            }
+        }
+    }
+}
+
+impl TryFrom<RawBytes> for MaskedBytes {
+    type Error = anyhow::Error;
+    fn try_from(value: RawBytes) -> Result<Self> {
+        match value {
+            RawBytes::Text(data) | RawBytes::Typed(RawTypedBytes::Text { data }) => {
+                Ok(MaskedBytes::exact(data.into_bytes()))
+            }
+            RawBytes::Typed(RawTypedBytes::Hex { data, mask: None }) => {
+                MaskedBytes::parse_hex(&data)
+            }
+            RawBytes::Typed(RawTypedBytes::Hex {
+                data,
+                mask: Some(mask),
+            }) => MaskedBytes::parse_hex(&data)?
+                .with_mask(MaskedBytes::parse_hex(&mask)?.value().to_vec()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawExpectation {
+    #[serde(default)]
+    prefix: Option<RawBytes>,
+    #[serde(default)]
+    exact: Option<RawBytes>,
+    #[serde(default)]
+    regex: Option<String>,
+    #[serde(default)]
+    json: BTreeMap<String, RawJsonAssertion>,
+    #[serde(default)]
+    capture: BTreeMap<String, RawCapture>,
+    #[serde(default)]
+    measure: BTreeMap<String, RawMeasurement>,
+}
+
+impl RawExpectation {
+    fn is_empty(&self) -> bool {
+        self.prefix.is_none()
+            && self.exact.is_none()
+            && self.regex.is_none()
+            && self.json.is_empty()
+    }
+
+    // field is prepended to names in errors so they match what was written in the scenario
+    fn validate(&self, field: &str) -> Result<()> {
+        if self.is_empty() {
+            bail!("{field}prefix, {field}regex or {field}json is needed to pick out the response");
+        }
+        // An empty value would match anything, which is never what was meant
+        for (name, value) in [("prefix", &self.prefix), ("exact", &self.exact)] {
+            if value.as_ref().is_some_and(RawBytes::is_empty) {
+                bail!("{field}{name} can't be empty");
+            }
+        }
+        if self.exact.is_some() && self.prefix.is_none() {
+            bail!("{field}exact needs an {field}prefix to pick out the response");
+        }
+        // The prefix only picks out the response, something still has to check it
+        if self.prefix.is_some()
+            && self.exact.is_none()
+            && self.regex.is_none()
+            && self.json.is_empty()
+        {
+            bail!(
+                "{field}prefix needs an {field}exact, {field}regex or {field}json to check against"
+            );
+        }
+        Ok(())
+    }
+
+    fn into_expectation(self, field: &str) -> Result<Expectation> {
+        let regex = self
+            .regex
+            .map(|pattern| {
+                Pattern::new(&pattern).context(format!("Invalid {field}regex {pattern:?}"))
+            })
+            .transpose()?;
+        let capture = self
+            .capture
+            .into_iter()
+            .map(|(name, source)| source.into_capture(name, regex.as_ref()))
+            .collect::<Result<Vec<Capture>>>()?;
+        let measure = self
+            .measure
+            .into_iter()
+            .map(|(name, source)| source.into_measurement(name, regex.as_ref()))
+            .collect::<Result<Vec<Measurement>>>()?;
+        let prefix = self
+            .prefix
+            .map(MaskedBytes::try_from)
+            .transpose()
+            .context(format!("Invalid {field}prefix"))?;
+        let exact = self
+            .exact
+            .map(MaskedBytes::try_from)
+            .transpose()
+            .context(format!("Invalid {field}exact"))?;
+        let json = self
+            .json
+            .into_iter()
+            .map(|(path, assertion)| {
+                assertion
+                    .into_assertion(&path)
+                    .context(format!("Invalid {field}json path {path:?}"))
+            })
+            .collect::<Result<Vec<JsonAssertion>>>()?;
+        Ok(Expectation {
+            prefix: prefix.unwrap_or_default(),
+            exact: exact.unwrap_or_default(),
+            regex,
+            json,
+            capture,
+            measure,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpectOrder {
+    #[default]
+    InOrder,
+    AnyOrder,
 }
 
 #[derive(Deserialize)]
@@ -16,10 +247,34 @@ enum RawSendable {
 enum RawDestination {
     Connection {
         send: Option<RawSendable>,
-        expect_prefix: Option<String>,
-        expect_exact: Option<String>,
-        timeout: Option<u64>,
-        delay: Option<u64>,
+        expect_prefix: Option<RawBytes>,
+        expect_exact: Option<RawBytes>,
+        #[serde(default)]
+        expect_regex: Option<String>,
+        #[serde(default)]
+        expect_json: BTreeMap<String, RawJsonAssertion>,
+        #[serde(default)]
+        capture: BTreeMap<String, RawCapture>,
+        #[serde(default)]
+        measure: BTreeMap<String, RawMeasurement>,
+        #[serde(default)]
+        expect: Vec<RawExpectation>,
+        #[serde(default)]
+        expect_order: ExpectOrder,
+        #[serde(default)]
+        expect_absent: Vec<String>,
+        #[serde(default)]
+        max_latency: Option<RawDuration>,
+        #[serde(default)]
+        min_latency: Option<RawDuration>,
+        timeout: Option<RawDuration>,
+        delay: Option<RawDuration>,
+        #[serde(default)]
+        retries: u32,
+        #[serde(default)]
+        retry_delay: Option<RawDuration>,
+        #[serde(default)]
+        repeat_until: Option<RawDuration>,
     },
 }
 
@@ -31,56 +286,269 @@ struct RawCommand {
     description: Option<String>,
 }
 
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRepeat {
+    #[serde(default)]
+    times: Option<u32>,
+    #[serde(default)]
+    duration: Option<RawDuration>,
+    commands: Vec<RawStep>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRepeatStep {
+    repeat: RawRepeat,
+}
+
+enum RawStep {
+    Command(Box<RawCommand>),
+    Repeat(RawRepeat),
+}
+
+// Picked by key rather than untagged, so a broken command still gets serde's own error message
+impl<'de> Deserialize<'de> for RawStep {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let step = match value.get("repeat") {
+            Some(_) => serde_json::from_value::<RawRepeatStep>(value)
+                .map(|step| RawStep::Repeat(step.repeat)),
+            None => {
+                serde_json::from_value(value).map(|command| RawStep::Command(Box::new(command)))
+            }
+        };
+        step.map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawScenario {
+    #[serde(default)]
+    forbidden: Vec<String>,
+    commands: Vec<RawStep>,
+}
+
+fn check_resolved(unresolved: &[String]) -> Result<()> {
+    if !unresolved.is_empty() {
+        let names: Vec<String> = unresolved
+            .iter()
+            .map(|name| format!("${{{name}}}"))
+            .collect();
+        bail!("Unresolved placeholders {}", names.join(", "));
+    }
+    Ok(())
+}
+
 impl RawCommand {
+    // Names captured by earlier commands are left in the send for the handler to fill in
+    fn resolve(&mut self, definitions: &Definitions, captured: &HashSet<String>) -> Result<()> {
+        let keep_nothing = HashSet::new();
+        let mut unresolved: Vec<String> = Vec::new();
+        // Sends are parsed into segments once, so nothing in them gets read as a placeholder twice
+        let RawDestination::Connection { send, .. } = &mut self.command;
+        if let Some(raw) = send.take() {
+            let (sendable, missing) = raw.resolve(definitions, captured)?;
+            for name in missing {
+                if !unresolved.contains(&name) {
+                    unresolved.push(name);
+                }
+            }
+            *send = Some(RawSendable::Resolved(sendable));
+        }
+        let mut fill = |field: &mut String, keep: &HashSet<String>| {
+            let (resolved, missing) = variables::resolve(field, definitions, keep);
+            *field = resolved;
+            for name in missing {
+                if !unresolved.contains(&name) {
+                    unresolved.push(name);
+                }
+            }
+        };
+
+        if let Some(description) = &mut self.description {
+            fill(description, &keep_nothing);
+        }
+        match &mut self.command {
+            RawDestination::Connection {
+                expect_prefix,
+                expect_exact,
+                expect_regex,
+                expect_json,
+                expect,
+                expect_absent,
+                ..
+            } => {
+                let mut fields: Vec<&mut String> = Vec::new();
+                for (prefix, exact, regex, json) in
+                    [(expect_prefix, expect_exact, expect_regex, expect_json)]
+                        .into_iter()
+                        .chain(expect.iter_mut().map(|entry| {
+                            (
+                                &mut entry.prefix,
+                                &mut entry.exact,
+                                &mut entry.regex,
+                                &mut entry.json,
+                            )
+                        }))
+                {
+                    fields.extend(
+                        prefix
+                            .iter_mut()
+                            .chain(exact.iter_mut())
+                            .flat_map(RawBytes::strings_mut),
+                    );
+                    fields.extend(regex.as_mut());
+                    fields.extend(json.values_mut().flat_map(RawJsonAssertion::strings_mut));
+                }
+                fields.extend(expect_absent.iter_mut());
+                for field in fields {
+                    fill(field, &keep_nothing);
+                }
+            }
+        }
+
+        check_resolved(&unresolved)
+    }
+
     fn validate(&self) -> Result<()> {
         match &self.command {
             RawDestination::Connection {
                 expect_prefix,
                 expect_exact,
+                expect_regex,
+                expect_json,
+                capture,
+                measure,
+                expect,
+                expect_absent,
+                max_latency,
+                min_latency,
                 timeout,
+                retries,
+                retry_delay,
+                repeat_until,
                 ..
-            } => match (expect_prefix, expect_exact, timeout) {
-                (Some(_), Some(_), Some(_)) | (None, None, None) => Ok(()),
-                _ => bail!("expect_prefix, expect_exact, and timeout must all be provided or None"),
-            },
+            } => {
+                let single = expect_prefix.is_some()
+                    || expect_exact.is_some()
+                    || expect_regex.is_some()
+                    || !expect_json.is_empty()
+                    || !capture.is_empty()
+                    || !measure.is_empty();
+                if single && !expect.is_empty() {
+                    bail!(
+                        "expect can't be combined with expect_prefix, expect_exact, expect_regex, expect_json, capture or measure"
+                    );
+                }
+                // Absent patterns are watched for over the whole timeout
+                let expects = single || !expect.is_empty();
+                let listens = expects || !expect_absent.is_empty();
+                if listens != timeout.is_some() {
+                    bail!(
+                        "timeout must be provided if and only if a response is expected or expect_absent is set"
+                    );
+                }
+                if (max_latency.is_some() || min_latency.is_some()) && !expects {
+                    bail!("max_latency and min_latency need a response to measure");
+                }
+                let repeats = *retries > 0 || repeat_until.is_some();
+                if repeats && !expects {
+                    bail!("retries and repeat_until need a response to wait for");
+                }
+                if *retries > 0 && repeat_until.is_some() {
+                    bail!("retries can't be combined with repeat_until");
+                }
+                if retry_delay.is_some() && !repeats {
+                    bail!("retry_delay needs retries or repeat_until");
+                }
+                for (index, entry) in expect.iter().enumerate() {
+                    entry
+                        .validate("")
+                        .with_context(|| format!("expect entry {index}"))?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Sendable {
-    Hex { data: Vec<u8> },
-    Text { data: Vec<u8> },
+    Hex { data: Vec<Segment> },
+    Text { data: Vec<Segment> },
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Destination {
     Connection {
         send: Sendable,
-        expect_prefix: Vec<u8>,
-        expect_exact: Vec<u8>,
+        expect: Vec<Expectation>,
+        expect_order: ExpectOrder,
+        expect_absent: Vec<Pattern>,
+        max_latency: Option<Duration>,
+        min_latency: Option<Duration>,
         timeout: Duration,
         delay: Duration,
+        retries: u32,
+        retry_delay: Duration,
+        repeat_until: Option<Duration>,
     },
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Command {
     pub command: Destination,
     pub description: Option<String>,
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Repeat {
+    Times(u32),
+    For(Duration),
+}
+
+impl Repeat {
+    pub fn keeps_going(&self, completed: u32, started: Instant) -> bool {
+        match *self {
+            Repeat::Times(times) => completed < times,
+            Repeat::For(duration) => started.elapsed() < duration,
+        }
+    }
+}
+
+// Steps refer to commands by their position in the file, so a command repeated many times keeps one index
+#[derive(Debug, PartialEq)]
+pub enum Step {
+    Command(usize),
+    Repeat { repeat: Repeat, steps: Vec<Step> },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Scenario {
+    pub forbidden: Vec<Pattern>,
+    pub commands: Vec<Command>,
+    pub steps: Vec<Step>,
+}
+
+impl Sendable {
+    // Fills in ${name} placeholders with values captured earlier in the scenario
+    pub fn render(&self, variables: &Variables) -> Result<Vec<u8>> {
+        match self {
+            Sendable::Hex { data } | Sendable::Text { data } => {
+                variables::substitute(data, variables)
+            }
+        }
+    }
+}
+
 impl TryFrom<RawSendable> for Sendable {
     type Error = anyhow::Error;
     fn try_from(value: RawSendable) -> Result<Self> {
-        Ok(match value {
-            RawSendable::Hex { data } => Sendable::Hex {
-                data: hex::decode(data)?,
-            },
-            RawSendable::Text { data } => Sendable::Text {
-                data: data.into_bytes(),
-            },
-        })
+        let (sendable, unresolved) = value.resolve(&Definitions::new(), &HashSet::new())?;
+        check_resolved(&unresolved)?;
+        Ok(sendable)
     }
 }
 
@@ -93,42 +561,215 @@ impl TryFrom<RawCommand> for Command {
                     send,
                     expect_prefix,
                     expect_exact,
+                    expect_regex,
+                    expect_json,
+                    capture,
+                    measure,
+                    expect,
+                    expect_order,
+                    expect_absent,
+                    max_latency,
+                    min_latency,
                     timeout,
                     delay,
-                } => Destination::Connection {
-                    send: send
-                        .map(Sendable::try_from)
-                        .unwrap_or(Ok(Sendable::Text { data: Vec::new() }))?,
-                    expect_prefix: expect_prefix
-                        .map(|value| value.into_bytes())
-                        .unwrap_or(Vec::new()),
-                    expect_exact: expect_exact
-                        .map(|value| value.into_bytes())
-                        .unwrap_or(Vec::new()),
-                    timeout: timeout
-                        .map(Duration::from_secs)
-                        .unwrap_or(Duration::from_secs(0)),
-                    delay: delay
-                        .map(Duration::from_secs)
-                        .unwrap_or(Duration::from_secs(0)),
-                },
+                    retries,
+                    retry_delay,
+                    repeat_until,
+                } => {
+                    // The expect_* fields are shorthand for a list with a single entry
+                    let single = RawExpectation {
+                        prefix: expect_prefix,
+                        exact: expect_exact,
+                        regex: expect_regex,
+                        json: expect_json,
+                        capture,
+                        measure,
+                    };
+                    let expect = match single.is_empty()
+                        && single.capture.is_empty()
+                        && single.measure.is_empty()
+                    {
+                        false => {
+                            single.validate("expect_")?;
+                            vec![single.into_expectation("expect_")?]
+                        }
+                        true => expect
+                            .into_iter()
+                            .enumerate()
+                            .map(|(index, entry)| {
+                                entry
+                                    .into_expectation("")
+                                    .with_context(|| format!("expect entry {index}"))
+                            })
+                            .collect::<Result<Vec<Expectation>>>()?,
+                    };
+                    let max_latency = max_latency
+                        .map(Duration::try_from)
+                        .transpose()
+                        .context("Invalid max_latency")?;
+                    let min_latency = min_latency
+                        .map(Duration::try_from)
+                        .transpose()
+                        .context("Invalid min_latency")?;
+                    if let (Some(min), Some(max)) = (min_latency, max_latency)
+                        && min > max
+                    {
+                        bail!("min_latency {min:?} is more than max_latency {max:?}");
+                    }
+                    Destination::Connection {
+                        send: send
+                            .map(Sendable::try_from)
+                            .unwrap_or(Ok(Sendable::Text { data: Vec::new() }))?,
+                        expect,
+                        expect_order,
+                        expect_absent: expect_absent
+                            .into_iter()
+                            .map(|pattern| {
+                                Pattern::new(&pattern)
+                                    .context(format!("Invalid expect_absent {pattern:?}"))
+                            })
+                            .collect::<Result<Vec<Pattern>>>()?,
+                        max_latency,
+                        min_latency,
+                        timeout: timeout
+                            .map(Duration::try_from)
+                            .transpose()
+                            .context("Invalid timeout")?
+                            .unwrap_or_default(),
+                        delay: delay
+                            .map(Duration::try_from)
+                            .transpose()
+                            .context("Invalid delay")?
+                            .unwrap_or_default(),
+                        retries,
+                        retry_delay: retry_delay
+                            .map(Duration::try_from)
+                            .transpose()
+                            .context("Invalid retry_delay")?
+                            .unwrap_or_default(),
+                        repeat_until: repeat_until
+                            .map(Duration::try_from)
+                            .transpose()
+                            .context("Invalid repeat_until")?,
+                    }
+                }
             },
             description: value.description,
         })
     }
 }
 
-pub fn parse_scenario(scenario: &PathBuf) -> Result<Vec<Command>> {
-    let file = File::open(scenario)?;
-    let reader = BufReader::new(file);
+pub fn parse_scenario(scenario: &PathBuf, definitions: &Definitions) -> Result<Scenario> {
+    let contents = fs::read_to_string(scenario)?;
+
+    // A bare list of commands is a scenario without any scenario wide settings
+    let raw_scenario = match contents.trim_start().starts_with('[') {
+        true => RawScenario {
+            forbidden: Vec::new(),
+            commands: serde_json::from_str(&contents)?,
+        },
+        false => serde_json::from_str(&contents)?,
+    };
+
+    let forbidden = raw_scenario
+        .forbidden
+        .into_iter()
+        .map(|pattern| {
+            let (pattern, unresolved) = variables::resolve(&pattern, definitions, &HashSet::new());
+            check_resolved(&unresolved)?;
+            Pattern::new(&pattern).context(format!("Invalid forbidden pattern {pattern:?}"))
+        })
+        .collect::<Result<Vec<Pattern>>>()
+        .with_context(|| format!("{} forbidden", scenario.display()))?;
 
-    let raw_commands: Vec<RawCommand> = serde_json::from_reader(reader)?;
     let mut processed_commands: Vec<Command> = vec![];
-    for raw_command in raw_commands {
-        raw_command.validate()?;
-        processed_commands.push(Command::try_from(raw_command)?);
+    let mut captured: HashSet<String> = HashSet::new();
+    let steps = parse_steps(
+        scenario,
+        raw_scenario.commands,
+        definitions,
+        &mut captured,
+        &mut processed_commands,
+    )?;
+    Ok(Scenario {
+        forbidden,
+        commands: processed_commands,
+        steps,
+    })
+}
+
+fn parse_steps(
+    scenario: &PathBuf,
+    raw_steps: Vec<RawStep>,
+    definitions: &Definitions,
+    captured: &mut HashSet<String>,
+    commands: &mut Vec<Command>,
+) -> Result<Vec<Step>> {
+    let mut steps = Vec::new();
+    for raw_step in raw_steps {
+        match raw_step {
+            RawStep::Command(mut raw_command) => {
+                let index = commands.len();
+                let command = raw_command
+                    .resolve(definitions, captured)
+                    .and_then(|_| raw_command.validate())
+                    .and_then(|_| Command::try_from(*raw_command))
+                    .with_context(|| format!("{} command {index}", scenario.display()))?;
+                let Destination::Connection { expect, .. } = &command.command;
+                captured.extend(
+                    expect
+                        .iter()
+                        .flat_map(|expectation| &expectation.capture)
+                        .map(|value| value.name.clone()),
+                );
+                commands.push(command);
+                steps.push(Step::Command(index));
+            }
+            RawStep::Repeat(raw_repeat) => {
+                let location = format!(
+                    "{} repeat before command {}",
+                    scenario.display(),
+                    commands.len()
+                );
+                let repeat = match (raw_repeat.times, raw_repeat.duration) {
+                    (Some(times), None) => Ok(Repeat::Times(times)),
+                    (None, Some(duration)) => Duration::try_from(duration)
+                        .map(Repeat::For)
+                        .context("Invalid duration"),
+                    _ => Err(anyhow!("repeat needs either times or duration")),
+                }
+                .context(location.clone())?;
+                let repeated = parse_steps(
+                    scenario,
+                    raw_repeat.commands,
+                    definitions,
+                    captured,
+                    commands,
+                )?;
+                // Without anything to wait on, a timed repeat would spin and pile up results until it ran out
+                if matches!(repeat, Repeat::For(_)) && !is_paced(&repeated, commands) {
+                    bail!(
+                        "{location}: duration needs a command with a delay or a timeout to pace it"
+                    );
+                }
+                steps.push(Step::Repeat {
+                    repeat,
+                    steps: repeated,
+                });
+            }
+        }
     }
-    Ok(processed_commands)
+    Ok(steps)
+}
+
+fn is_paced(steps: &[Step], commands: &[Command]) -> bool {
+    steps.iter().any(|step| match step {
+        Step::Command(index) => {
+            let Destination::Connection { timeout, delay, .. } = &commands[*index].command;
+            !timeout.is_zero() || !delay.is_zero()
+        }
+        Step::Repeat { steps, .. } => is_paced(steps, commands),
+    })
 }
 
 #[cfg(test)]
@@ -137,6 +778,7 @@ mod tests {
     use std::io::Write;
 
     use super::*;
+    use crate::interaction::measurement::Bounds;
     use tempfile::NamedTempFile;
 
     #[test]
@@ -164,21 +806,40 @@ mod tests {
             .expect("Failed to write JSON");
         let scenario = temp_file.path().to_path_buf();
 
-        let result = parse_scenario(&scenario).expect("Failed to parse scenario");
+        let result =
+            parse_scenario(&scenario, &Definitions::new()).expect("Failed to parse scenario");
         let assert_command = Command {
             command: Destination::Connection {
                 send: Sendable::Text {
-                    data: Vec::from("Hello"),
+                    data: vec![Segment::Literal(Vec::from("Hello"))],
                 },
-                expect_prefix: Vec::from("This is the fixed sentence that always"),
-                expect_exact: Vec::from("This is the fixed sentence that always appears"),
+                expect: vec![Expectation {
+                    prefix: MaskedBytes::exact(Vec::from("This is the fixed sentence that always")),
+                    exact: MaskedBytes::exact(Vec::from(
+                        "This is the fixed sentence that always appears",
+                    )),
+                    regex: None,
+                    json: Vec::new(),
+                    capture: Vec::new(),
+                    measure: Vec::new(),
+                }],
+                expect_order: ExpectOrder::InOrder,
+                expect_absent: Vec::new(),
+                max_latency: None,
+                min_latency: None,
                 timeout: Duration::from_secs(240),
                 delay: Duration::from_secs(0),
+                retries: 0,
+                retry_delay: Duration::ZERO,
+                repeat_until: None,
             },
             description: None,
         };
 
-        assert_eq!(result[0], assert_command, "Failed to parse scenario");
+        assert_eq!(
+            result.commands[0], assert_command,
+            "Failed to parse scenario"
+        );
     }
 
     #[test]
@@ -219,34 +880,71 @@ mod tests {
             .expect("Failed to write JSON");
         let scenario = temp_file.path().to_path_buf();
 
-        let result = parse_scenario(&scenario).expect("Failed to parse scenario");
+        let result =
+            parse_scenario(&scenario, &Definitions::new()).expect("Failed to parse scenario");
         let assert_text_command = Command {
             command: Destination::Connection {
                 send: Sendable::Text {
-                    data: Vec::from("Hello"),
+                    data: vec![Segment::Literal(Vec::from("Hello"))],
                 },
-                expect_prefix: Vec::from("This is the fixed sentence that always"),
-                expect_exact: Vec::from("This is the fixed sentence that always appears"),
+                expect: vec![Expectation {
+                    prefix: MaskedBytes::exact(Vec::from("This is the fixed sentence that always")),
+                    exact: MaskedBytes::exact(Vec::from(
+                        "This is the fixed sentence that always appears",
+                    )),
+                    regex: None,
+                    json: Vec::new(),
+                    capture: Vec::new(),
+                    measure: Vec::new(),
+                }],
+                expect_order: ExpectOrder::InOrder,
+                expect_absent: Vec::new(),
+                max_latency: None,
+                min_latency: None,
                 timeout: Duration::from_secs(240),
                 delay: Duration::from_secs(0),
+                retries: 0,
+                retry_delay: Duration::ZERO,
+                repeat_until: None,
             },
             description: None,
         };
         let assert_hex_command = Command {
             command: Destination::Connection {
                 send: Sendable::Hex {
-                    data: vec![0xde, 0xad, 0xbe, 0xef],
+                    data: vec![Segment::Literal(vec![0xde, 0xad, 0xbe, 0xef])],
                 },
-                expect_prefix: Vec::from("This is the fixed sentence that always"),
-                expect_exact: Vec::from("This is the fixed sentence that always appears"),
+                expect: vec![Expectation {
+                    prefix: MaskedBytes::exact(Vec::from("This is the fixed sentence that always")),
+                    exact: MaskedBytes::exact(Vec::from(
+                        "This is the fixed sentence that always appears",
+                    )),
+                    regex: None,
+                    json: Vec::new(),
+                    capture: Vec::new(),
+                    measure: Vec::new(),
+                }],
+                expect_order: ExpectOrder::InOrder,
+                expect_absent: Vec::new(),
+                max_latency: None,
+                min_latency: None,
                 timeout: Duration::from_secs(240),
                 delay: Duration::from_secs(0),
+                retries: 0,
+                retry_delay: Duration::ZERO,
+                repeat_until: None,
             },
             description: None,
         };
 
-        assert_eq!(result[0], assert_text_command, "Failed to parse scenario");
-        assert_eq!(result[1], assert_hex_command, "Failed to parse scenario");
+        assert_eq!(
+            result.commands[0], assert_text_command,
+            "Failed to parse scenario"
+        );
+        assert_eq!(
+            result.commands[1], assert_hex_command,
+            "Failed to parse scenario"
+        );
     }
 
     #[test]
@@ -286,7 +984,976 @@ mod tests {
             .expect("Failed to write JSON");
         let scenario = temp_file.path().to_path_buf();
 
-        let result = parse_scenario(&scenario);
+        let result = parse_scenario(&scenario, &Definitions::new());
         assert!(result.is_err(), "Somehow the JSON was actually valid");
     }
+
+    fn write_scenario(raw_json: &str) -> NamedTempFile {
+        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        temp_file
+            .write_all(raw_json.as_bytes())
+            .expect("Failed to write JSON");
+        temp_file
+    }
+
+    #[test]
+    fn parse_scenario_regex_pass() {
+        let temp_file = write_scenario(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "GET_ID" },
+                        "expect_regex": "^ID:[0-9A-F]{8}$",
+                        "timeout": 1
+                    }
+                },
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "STATUS" },
+                        "expect_prefix": "STATUS",
+                        "expect_regex": "T=\\d+",
+                        "timeout": 1
+                    }
+                }
+            ]
+            "#,
+        );
+
+        let result = parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new())
+            .expect("Failed to parse");
+
+        assert_eq!(
+            result.commands[0].command,
+            Destination::Connection {
+                send: Sendable::Text {
+                    data: vec![Segment::Literal(Vec::from("GET_ID"))],
+                },
+                expect: vec![Expectation {
+                    prefix: MaskedBytes::default(),
+                    exact: MaskedBytes::default(),
+                    regex: Some(Pattern::new("^ID:[0-9A-F]{8}$").expect("Failed to compile")),
+                    json: Vec::new(),
+                    capture: Vec::new(),
+                    measure: Vec::new(),
+                }],
+                expect_order: ExpectOrder::InOrder,
+                expect_absent: Vec::new(),
+                max_latency: None,
+                min_latency: None,
+                timeout: Duration::from_secs(1),
+                delay: Duration::from_secs(0),
+                retries: 0,
+                retry_delay: Duration::ZERO,
+                repeat_until: None,
+            }
+        );
+        let Destination::Connection { expect, .. } = &result.commands[1].command;
+        assert_eq!(expect[0].prefix, MaskedBytes::exact(Vec::from("STATUS")));
+        assert!(expect[0].regex.is_some());
+    }
+
+    #[test]
+    fn parse_scenario_regex_fail_names_command() {
+        let temp_file = write_scenario(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "PING" }
+                    }
+                },
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "GET_ID" },
+                        "expect_regex": "ID:(",
+                        "timeout": 1
+                    }
+                }
+            ]
+            "#,
+        );
+        let scenario = temp_file.path().to_path_buf();
+
+        let error = parse_scenario(&scenario, &Definitions::new())
+            .expect_err("Somehow the pattern compiled");
+        let message = format!("{error:#}");
+
+        assert!(message.contains(&format!("{} command 1", scenario.display())));
+        assert!(message.contains("Invalid expect_regex \"ID:(\""));
+    }
+
+    #[test]
+    fn parse_scenario_regex_fail_without_timeout() {
+        let temp_file = write_scenario(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "GET_ID" },
+                        "expect_regex": "ID"
+                    }
+                }
+            ]
+            "#,
+        );
+
+        assert!(
+            parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new()).is_err(),
+            "Somehow a regex without a timeout was accepted"
+        );
+    }
+
+    #[test]
+    fn parse_scenario_capture_pass() {
+        let temp_file = write_scenario(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Hex", "data": "aa55" },
+                        "expect_regex": "^ID:(?<id>[0-9A-F]+)$",
+                        "capture": {
+                            "device_id": { "group": "id" },
+                            "kind": { "offset": 3, "length": 2 }
+                        },
+                        "timeout": 1
+                    }
+                },
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Hex", "data": "01${kind}" }
+                    }
+                }
+            ]
+            "#,
+        );
+
+        let result = parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new())
+            .expect("Failed to parse");
+
+        let Destination::Connection { expect, .. } = &result.commands[0].command;
+        assert_eq!(
+            expect[0].capture,
+            vec![
+                Capture {
+                    name: String::from("device_id"),
+                    source: CaptureSource::Group(String::from("id")),
+                },
+                Capture {
+                    name: String::from("kind"),
+                    source: CaptureSource::Bytes {
+                        offset: 3,
+                        length: 2
+                    },
+                },
+            ]
+        );
+        let Destination::Connection { send, .. } = &result.commands[1].command;
+        assert_eq!(
+            send,
+            &Sendable::Hex {
+                data: vec![
+                    Segment::Literal(vec![0x01]),
+                    Segment::Placeholder(String::from("kind"))
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn parse_scenario_hex_placeholder_bytes_pass() {
+        // 24 7b 69 64 7d spells ${id}, a capture of that name must not change what's sent
+        let temp_file = write_scenario(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "GET_ID" },
+                        "expect_regex": "^ID:(?<id>[0-9A-F]+)$",
+                        "capture": { "id": { "group": "id" } },
+                        "timeout": 1
+                    }
+                },
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Hex", "data": "aa247b69647d01" }
+                    }
+                }
+            ]
+            "#,
+        );
+
+        let result = parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new())
+            .expect("Failed to parse");
+
+        let Destination::Connection { send, .. } = &result.commands[1].command;
+        let variables = Variables::from([(String::from("id"), Vec::from("00C0FFEE"))]);
+        assert_eq!(
+            send.render(&variables).expect("Failed to render"),
+            b"\xaa${id}\x01"
+        );
+        assert_eq!(
+            send.render(&Variables::new()).expect("Failed to render"),
+            b"\xaa${id}\x01"
+        );
+    }
+
+    #[test]
+    fn parse_scenario_capture_fail_unknown_group() {
+        let temp_file = write_scenario(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "GET_ID" },
+                        "expect_regex": "^ID:(?<id>[0-9A-F]+)$",
+                        "capture": { "device_id": { "group": "serial" } },
+                        "timeout": 1
+                    }
+                }
+            ]
+            "#,
+        );
+
+        let error = parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new())
+            .expect_err("Somehow captured from a group that doesn't exist");
+
+        assert!(format!("{error:#}").contains("group named serial"));
+    }
+
+    #[test]
+    fn parse_scenario_capture_fail_used_before_captured() {
+        let temp_file = write_scenario(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "SET ${device_id}" }
+                    }
+                },
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "GET_ID" },
+                        "expect_regex": "^ID:(?<id>[0-9A-F]+)$",
+                        "capture": { "device_id": { "group": "id" } },
+                        "timeout": 1
+                    }
+                }
+            ]
+            "#,
+        );
+
+        let error = parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new())
+            .expect_err("Somehow used a variable before it was captured");
+        let message = format!("{error:#}");
+
+        assert!(message.contains("command 0"));
+        assert!(message.contains("${device_id}"));
+    }
+
+    #[test]
+    fn parse_scenario_variables_pass() {
+        let temp_file = write_scenario(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "GET_ID ${model}" },
+                        "expect_prefix": "${model}",
+                        "expect_regex": "^${model} (?<id>[0-9A-F]+)$",
+                        "capture": { "model": { "group": "id" } },
+                        "timeout": 1
+                    },
+                    "description": "Identify the ${model}"
+                },
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Hex", "data": "${header}${model}" }
+                    }
+                }
+            ]
+            "#,
+        );
+        let definitions = Definitions::from([
+            (String::from("model"), String::from("X200")),
+            (String::from("header"), String::from("aa55")),
+        ]);
+
+        let result =
+            parse_scenario(&temp_file.path().to_path_buf(), &definitions).expect("Failed to parse");
+
+        assert_eq!(
+            result.commands[0].description.as_deref(),
+            Some("Identify the X200")
+        );
+        let Destination::Connection { send, expect, .. } = &result.commands[0].command;
+        assert_eq!(
+            send,
+            &Sendable::Text {
+                data: vec![Segment::Literal(Vec::from("GET_ID X200"))]
+            }
+        );
+        assert_eq!(expect[0].prefix, MaskedBytes::exact(Vec::from("X200")));
+        assert_eq!(
+            expect[0].regex.as_ref().map(Pattern::as_str),
+            Some("^X200 (?<id>[0-9A-F]+)$")
+        );
+        // Once captured, the scenario's own value wins over the definition
+        let Destination::Connection { send, .. } = &result.commands[1].command;
+        assert_eq!(
+            send,
+            &Sendable::Hex {
+                data: vec![
+                    Segment::Literal(vec![0xaa, 0x55]),
+                    Segment::Placeholder(String::from("model"))
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn parse_scenario_variables_fail_lists_unresolved() {
+        let temp_file = write_scenario(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "SET ${port} ${baud}" },
+                        "expect_prefix": "${port}",
+                        "expect_exact": "${port} ${model}",
+                        "timeout": 1
+                    }
+                }
+            ]
+            "#,
+        );
+        let definitions = Definitions::from([(String::from("baud"), String::from("9600"))]);
+
+        let error = parse_scenario(&temp_file.path().to_path_buf(), &definitions)
+            .expect_err("Somehow parsed with placeholders left over");
+
+        assert!(format!("{error:#}").contains("Unresolved placeholders ${port}, ${model}"));
+    }
+
+    #[test]
+    fn parse_scenario_expect_list_pass() {
+        let temp_file = write_scenario(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "STATUS" },
+                        "expect": [
+                            { "prefix": "MODE", "exact": "MODE idle" },
+                            { "regex": "^T=(?<temperature>\\d+)$", "capture": { "temperature": { "group": "temperature" } } },
+                            { "prefix": "OK", "regex": "^OK$" }
+                        ],
+                        "expect_order": "any_order",
+                        "timeout": 1
+                    }
+                }
+            ]
+            "#,
+        );
+
+        let result = parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new())
+            .expect("Failed to parse");
+
+        let Destination::Connection {
+            expect,
+            expect_order,
+            ..
+        } = &result.commands[0].command;
+        assert_eq!(expect_order, &ExpectOrder::AnyOrder);
+        assert_eq!(expect.len(), 3);
+        assert_eq!(expect[0].exact, MaskedBytes::exact(Vec::from("MODE idle")));
+        assert_eq!(expect[1].capture[0].name, "temperature");
+        assert_eq!(expect[2].regex.as_ref().map(Pattern::as_str), Some("^OK$"));
+    }
+
+    #[test]
+    fn parse_scenario_expect_list_fail() {
+        let invalid_commands = [
+            // Mixed with the single response shorthand
+            r#"{ "expect_prefix": "OK", "expect_exact": "OK", "expect": [{ "regex": "T" }], "timeout": 1 }"#,
+            // Nothing to pick the response out with
+            r#"{ "expect": [{ "exact": "OK" }], "timeout": 1 }"#,
+            // Prefix without anything to check
+            r#"{ "expect": [{ "regex": "T" }, { "prefix": "OK" }], "timeout": 1 }"#,
+            r#"{ "expect": [{ "regex": "T" }] }"#,
+            r#"{ "expect": [{ "regex": "T", "suffix": "OK" }], "timeout": 1 }"#,
+            r#"{ "expect": [{ "regex": "T" }], "expect_order": "sorted", "timeout": 1 }"#,
+        ];
+        for fields in invalid_commands {
+            let fields = fields.trim_start_matches('{');
+            let temp_file = write_scenario(&format!(
+                r#"[{{ "command": {{ "destination": "Connection", {fields} }}]"#
+            ));
+
+            assert!(
+                parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new()).is_err(),
+                "Somehow parsed {fields}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_scenario_empty_expectation_fail() {
+        let invalid_commands = [
+            r#"{ "expect_prefix": "", "expect_exact": "OK", "timeout": 1 }"#,
+            r#"{ "expect_prefix": "OK", "expect_exact": "", "timeout": 1 }"#,
+            r#"{ "expect_prefix": { "type": "Hex", "data": " " }, "expect_regex": "T", "timeout": 1 }"#,
+            r#"{ "expect": [{ "prefix": "OK", "exact": { "type": "Text", "data": "" } }], "timeout": 1 }"#,
+            r#"{ "expect_prefix": "${empty}", "expect_exact": "OK", "timeout": 1 }"#,
+        ];
+        let definitions = Definitions::from([(String::from("empty"), String::new())]);
+        for fields in invalid_commands {
+            let fields = fields.trim_start_matches('{');
+            let temp_file = write_scenario(&format!(
+                r#"[{{ "command": {{ "destination": "Connection", {fields} }}]"#
+            ));
+
+            let error = parse_scenario(&temp_file.path().to_path_buf(), &definitions)
+                .expect_err(&format!("Somehow parsed {fields}"));
+            assert!(
+                format!("{error:#}").contains("can't be empty"),
+                "Failed for another reason: {error:#}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_scenario_hex_expectations_pass() {
+        let temp_file = write_scenario(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Hex", "data": "aa5501" },
+                        "expect": [
+                            {
+                                "prefix": { "type": "Hex", "data": "AA 55" },
+                                "exact": { "type": "Hex", "data": "AA 55 ?? ?? 01" }
+                            },
+                            {
+                                "prefix": { "type": "Text", "data": "S" },
+                                "exact": { "type": "Hex", "data": "53 ${status} ff", "mask": "ff\tff\n0f" }
+                            }
+                        ],
+                        "timeout": 1
+                    }
+                }
+            ]
+            "#,
+        );
+        let definitions = Definitions::from([(String::from("status"), String::from("4f"))]);
+
+        let result =
+            parse_scenario(&temp_file.path().to_path_buf(), &definitions).expect("Failed to parse");
+
+        let Destination::Connection { expect, .. } = &result.commands[0].command;
+        assert_eq!(expect[0].prefix, MaskedBytes::exact(vec![0xaa, 0x55]));
+        assert_eq!(expect[0].exact.to_hex(), "aa55????01");
+        assert_eq!(expect[1].prefix, MaskedBytes::exact(Vec::from("S")));
+        assert_eq!(expect[1].exact.to_hex(), "534f?f");
+        assert!(expect[1].matches(&[0x53, 0x4f, 0x3f]));
+    }
+
+    #[test]
+    fn parse_scenario_hex_expectations_fail() {
+        let invalid_expectations = [
+            r#"{ "prefix": { "type": "Hex", "data": "AA5" }, "exact": "x" }"#,
+            r#"{ "prefix": "AA", "exact": { "type": "Hex", "data": "AAZZ" } }"#,
+            r#"{ "prefix": "AA", "exact": { "type": "Hex", "data": "AA55", "mask": "ff" } }"#,
+            r#"{ "prefix": "AA", "exact": { "type": "Text", "data": "AA", "mask": "ff" } }"#,
+            r#"{ "prefix": "AA", "exact": { "type": "Base64", "data": "qlU=" } }"#,
+        ];
+        for entry in invalid_expectations {
+            let temp_file = write_scenario(&format!(
+                r#"[{{ "command": {{ "destination": "Connection", "expect": [{entry}], "timeout": 1 }} }}]"#
+            ));
+
+            assert!(
+                parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new()).is_err(),
+                "Somehow parsed {entry}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_scenario_expect_json_pass() {
+        let temp_file = write_scenario(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "expect_json": { "model": { "equals": "${model}" } },
+                        "timeout": 1
+                    }
+                },
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "expect": [
+                            { "prefix": "{\"event\"", "json": { "readings": { "length": 3 } } },
+                            { "regex": "^\\{", "json": { "readings[0]": { "type": "number" } } }
+                        ],
+                        "timeout": 1
+                    }
+                }
+            ]
+            "#,
+        );
+        let definitions = Definitions::from([(String::from("model"), String::from("X200"))]);
+
+        let result =
+            parse_scenario(&temp_file.path().to_path_buf(), &definitions).expect("Failed to parse");
+
+        let Destination::Connection { expect, .. } = &result.commands[0].command;
+        assert!(expect[0].matches(br#"{"model": "X200", "serial": 7}"#));
+        assert!(!expect[0].matches(br#"{"model": "${model}"}"#));
+        let Destination::Connection { expect, .. } = &result.commands[1].command;
+        assert_eq!(
+            expect[0].prefix,
+            MaskedBytes::exact(Vec::from("{\"event\""))
+        );
+        assert!(expect[0].matches(br#"{"event": 1, "readings": [1, 2, 3]}"#));
+        assert!(expect[1].matches(br#"{"readings": [1.5]}"#));
+    }
+
+    #[test]
+    fn parse_scenario_expect_json_fail() {
+        let invalid_commands = [
+            r#""expect_json": { "a..b": { "present": true } }"#,
+            r#""expect_json": { "a": {} }"#,
+            r#""expect_json": { "a": { "present": false, "equals": 1 } }"#,
+            r#""expect_json": { "a": { "type": "date" } }"#,
+            r#""expect_json": { "a": { "present": true } }, "expect": [{ "regex": "x" }]"#,
+            r#""expect_json": { "a": { "equals": "${missing}" } }"#,
+        ];
+        for fields in invalid_commands {
+            let temp_file = write_scenario(&format!(
+                r#"[{{ "command": {{ "destination": "Connection", {fields}, "timeout": 1 }} }}]"#
+            ));
+
+            assert!(
+                parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new()).is_err(),
+                "Somehow parsed {fields}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_scenario_measure_pass() {
+        let temp_file = write_scenario(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "expect_regex": "^T=(?<temp>-?[0-9.]+)$",
+                        "measure": { "temperature": { "group": "temp", "max": 85 } },
+                        "timeout": 1
+                    }
+                },
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "expect": [
+                            {
+                                "prefix": { "type": "Hex", "data": "AA" },
+                                "exact": { "type": "Hex", "data": "AA ?? ??" },
+                                "measure": {
+                                    "voltage": { "offset": 1, "width": 2, "endian": "little", "scale": 0.5, "nominal": 5, "tolerance": 0.25 }
+                                }
+                            }
+                        ],
+                        "timeout": 1
+                    }
+                }
+            ]
+            "#,
+        );
+
+        let result = parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new())
+            .expect("Failed to parse");
+
+        let Destination::Connection { expect, .. } = &result.commands[0].command;
+        assert_eq!(expect[0].measure[0].name, "temperature");
+        assert_eq!(
+            expect[0].measure[0].bounds,
+            Some(Bounds::Range {
+                min: None,
+                max: Some(85.0)
+            })
+        );
+        let Destination::Connection { expect, .. } = &result.commands[1].command;
+        assert_eq!(
+            expect[0].measure[0].extract(&[0xaa, 0x0a, 0x00], None),
+            Some(5.0)
+        );
+    }
+
+    #[test]
+    fn parse_scenario_measure_fail() {
+        let invalid_commands = [
+            r#""measure": { "v": { "offset": 0, "width": 2 } }"#,
+            r#""expect_regex": "^V=(?<volts>.+)$", "measure": { "v": { "group": "amps" } }"#,
+            r#""expect_regex": "^V", "measure": { "v": { "offset": 0, "width": 2, "minimum": 1 } }"#,
+            r#""expect": [{ "regex": "^V" }], "measure": { "v": { "offset": 0, "width": 2 } }"#,
+        ];
+        for fields in invalid_commands {
+            let temp_file = write_scenario(&format!(
+                r#"[{{ "command": {{ "destination": "Connection", {fields}, "timeout": 1 }} }}]"#
+            ));
+
+            assert!(
+                parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new()).is_err(),
+                "Somehow parsed {fields}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_scenario_retries_pass() {
+        let temp_file = write_scenario(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "PING" },
+                        "expect_prefix": "PONG",
+                        "expect_exact": "PONG",
+                        "timeout": "500ms",
+                        "retries": 3,
+                        "retry_delay": "100ms"
+                    }
+                },
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "STATUS?" },
+                        "expect_regex": "^STATUS READY$",
+                        "timeout": 1,
+                        "repeat_until": "30s"
+                    }
+                }
+            ]
+            "#,
+        );
+
+        let result = parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new())
+            .expect("Failed to parse");
+
+        let Destination::Connection {
+            retries,
+            retry_delay,
+            repeat_until,
+            ..
+        } = &result.commands[0].command;
+        assert_eq!(
+            (*retries, *retry_delay, *repeat_until),
+            (3, Duration::from_millis(100), None)
+        );
+        let Destination::Connection {
+            retries,
+            retry_delay,
+            repeat_until,
+            ..
+        } = &result.commands[1].command;
+        assert_eq!(
+            (*retries, *retry_delay, *repeat_until),
+            (0, Duration::ZERO, Some(Duration::from_secs(30)))
+        );
+    }
+
+    #[test]
+    fn parse_scenario_retries_fail() {
+        let invalid_commands = [
+            r#""retries": 2"#,
+            r#""expect_regex": "^OK", "timeout": 1, "retries": 2, "repeat_until": "5s""#,
+            r#""expect_regex": "^OK", "timeout": 1, "retry_delay": "1s""#,
+            r#""expect_regex": "^OK", "timeout": 1, "repeat_until": "soon""#,
+            r#""expect_regex": "^OK", "timeout": 1, "retries": -1"#,
+        ];
+        for fields in invalid_commands {
+            let temp_file = write_scenario(&format!(
+                r#"[{{ "command": {{ "destination": "Connection", {fields} }} }}]"#
+            ));
+
+            assert!(
+                parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new()).is_err(),
+                "Somehow parsed {fields}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_scenario_repeat_pass() {
+        let temp_file = write_scenario(
+            r#"
+            {
+                "commands": [
+                    { "command": { "destination": "Connection" }, "description": "Wake up" },
+                    {
+                        "repeat": {
+                            "times": 1000,
+                            "commands": [
+                                { "command": { "destination": "Connection" }, "description": "Ping" },
+                                {
+                                    "repeat": {
+                                        "duration": "1.5h",
+                                        "commands": [
+                                            { "command": { "destination": "Connection", "delay": 1 }, "description": "Poll" }
+                                        ]
+                                    }
+                                }
+                            ]
+                        }
+                    },
+                    { "command": { "destination": "Connection" }, "description": "Sleep" }
+                ]
+            }
+            "#,
+        );
+
+        let result = parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new())
+            .expect("Failed to parse");
+
+        assert_eq!(
+            result
+                .commands
+                .iter()
+                .map(|command| command.description.as_deref())
+                .collect::<Vec<_>>(),
+            vec![Some("Wake up"), Some("Ping"), Some("Poll"), Some("Sleep")]
+        );
+        assert_eq!(
+            result.steps,
+            vec![
+                Step::Command(0),
+                Step::Repeat {
+                    repeat: Repeat::Times(1000),
+                    steps: vec![
+                        Step::Command(1),
+                        Step::Repeat {
+                            repeat: Repeat::For(Duration::from_secs(5400)),
+                            steps: vec![Step::Command(2)],
+                        },
+                    ],
+                },
+                Step::Command(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_scenario_repeat_fail() {
+        let command = r#"{ "command": { "destination": "Connection" } }"#;
+        let invalid_repeats = [
+            format!(r#"{{ "times": 2, "duration": "1m", "commands": [{command}] }}"#),
+            format!(r#"{{ "commands": [{command}] }}"#),
+            format!(r#"{{ "duration": "soon", "commands": [{command}] }}"#),
+            format!(r#"{{ "duration": "1m", "commands": [{command}] }}"#),
+            format!(
+                r#"{{ "duration": "1m", "commands": [{{ "repeat": {{ "times": 2, "commands": [{command}] }} }}] }}"#
+            ),
+            format!(r#"{{ "times": 2, "until": "1m", "commands": [{command}] }}"#),
+            String::from(
+                r#"{ "times": 2, "commands": [{ "command": { "destination": "Nowhere" } }] }"#,
+            ),
+        ];
+        for repeat in invalid_repeats {
+            let temp_file = write_scenario(&format!(r#"[{command}, {{ "repeat": {repeat} }}]"#));
+
+            assert!(
+                parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new()).is_err(),
+                "Somehow parsed {repeat}"
+            );
+        }
+    }
+
+    #[test]
+    fn repeat_keeps_going_pass() {
+        let started = Instant::now();
+
+        assert!(Repeat::Times(2).keeps_going(1, started));
+        assert!(!Repeat::Times(2).keeps_going(2, started));
+        assert!(Repeat::For(Duration::from_secs(60)).keeps_going(100, started));
+        assert!(!Repeat::For(Duration::ZERO).keeps_going(0, started));
+    }
+
+    #[test]
+    fn parse_scenario_forbidden_pass() {
+        let temp_file = write_scenario(
+            r#"
+            {
+                "forbidden": ["panic", "${level}:"],
+                "commands": [
+                    {
+                        "command": {
+                            "destination": "Connection",
+                            "send": { "type": "Text", "data": "RESET" },
+                            "expect_absent": ["assert"],
+                            "timeout": 1
+                        }
+                    }
+                ]
+            }
+            "#,
+        );
+        let definitions = Definitions::from([(String::from("level"), String::from("ERROR"))]);
+
+        let result =
+            parse_scenario(&temp_file.path().to_path_buf(), &definitions).expect("Failed to parse");
+
+        let forbidden: Vec<&str> = result.forbidden.iter().map(Pattern::as_str).collect();
+        assert_eq!(forbidden, vec!["panic", "ERROR:"]);
+        let Destination::Connection {
+            expect,
+            expect_absent,
+            ..
+        } = &result.commands[0].command;
+        assert!(expect.is_empty());
+        assert_eq!(
+            expect_absent,
+            &vec![Pattern::new("assert").expect("Failed to compile")]
+        );
+    }
+
+    #[test]
+    fn parse_scenario_forbidden_fail() {
+        let invalid_scenarios = [
+            r#"{ "forbidden": ["panic ("], "commands": [] }"#,
+            r#"{ "forbidden": ["${level}"], "commands": [] }"#,
+            r#"{ "forbidden": ["panic"] }"#,
+            r#"{ "forbidden": ["panic"], "commands": [], "allowed": [] }"#,
+            r#"[{ "command": { "destination": "Connection", "expect_absent": ["assert"] } }]"#,
+        ];
+        for raw_json in invalid_scenarios {
+            let temp_file = write_scenario(raw_json);
+
+            assert!(
+                parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new()).is_err(),
+                "Somehow parsed {raw_json}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_scenario_durations_pass() {
+        let temp_file = write_scenario(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "PING" },
+                        "expect_regex": "PONG",
+                        "timeout": "50ms",
+                        "max_latency": "20ms",
+                        "delay": 1.5
+                    }
+                }
+            ]
+            "#,
+        );
+
+        let result = parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new())
+            .expect("Failed to parse");
+
+        let Destination::Connection {
+            timeout,
+            delay,
+            max_latency,
+            ..
+        } = &result.commands[0].command;
+        assert_eq!(timeout, &Duration::from_millis(50));
+        assert_eq!(max_latency, &Some(Duration::from_millis(20)));
+        assert_eq!(delay, &Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn parse_scenario_latency_fail() {
+        let invalid_commands = [
+            r#"{ "max_latency": "50ms" }"#,
+            r#"{ "expect_absent": ["ERROR"], "min_latency": "5ms", "timeout": 1 }"#,
+            r#"{ "expect_regex": "OK", "min_latency": "50ms", "max_latency": "5ms", "timeout": 1 }"#,
+            r#"{ "expect_regex": "OK", "max_latency": "soon", "timeout": 1 }"#,
+        ];
+        for fields in invalid_commands {
+            let fields = fields.trim_start_matches('{');
+            let temp_file = write_scenario(&format!(
+                r#"[{{ "command": {{ "destination": "Connection", {fields} }}]"#
+            ));
+
+            assert!(
+                parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new()).is_err(),
+                "Somehow parsed {fields}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_scenario_durations_fail() {
+        let temp_file = write_scenario(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "PING" },
+                        "expect_regex": "PONG",
+                        "timeout": "50 parsecs"
+                    }
+                }
+            ]
+            "#,
+        );
+
+        let error = parse_scenario(&temp_file.path().to_path_buf(), &Definitions::new())
+            .expect_err("Somehow parsed a timeout without a known unit");
+
+        assert!(format!("{error:#}").contains("Invalid timeout"));
+    }
+
+    #[test]
+    fn sendable_render_pass() {
+        let sendable = Sendable::Text {
+            data: vec![
+                Segment::Literal(Vec::from("SET ")),
+                Segment::Placeholder(String::from("device_id")),
+            ],
+        };
+        let variables = Variables::from([(String::from("device_id"), Vec::from("00C0FFEE"))]);
+
+        assert_eq!(
+            sendable.render(&variables).expect("Failed to render"),
+            b"SET 00C0FFEE"
+        );
+        assert!(
+            sendable.render(&Variables::new()).is_err(),
+            "Somehow rendered without the variable"
+        );
+    }
 }
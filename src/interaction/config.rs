@@ -1,14 +1,268 @@
-use anyhow::{Ok, Result};
+use anyhow::{Ok, Result, bail};
 use serde::Deserialize;
+use serialport::{DataBits, StopBits};
+
+use super::variables::{self, Definitions};
+use crate::connection::framing::{Checksum, Endian, Layout};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io;
 use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TcpMode {
+    #[default]
+    Connect,
+    Listen,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+impl From<Parity> for serialport::Parity {
+    fn from(parity: Parity) -> Self {
+        match parity {
+            Parity::None => serialport::Parity::None,
+            Parity::Odd => serialport::Parity::Odd,
+            Parity::Even => serialport::Parity::Even,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowControl {
+    None,
+    Software,
+    Hardware,
+}
+
+impl From<FlowControl> for serialport::FlowControl {
+    fn from(flow_control: FlowControl) -> Self {
+        match flow_control {
+            FlowControl::None => serialport::FlowControl::None,
+            FlowControl::Software => serialport::FlowControl::Software,
+            FlowControl::Hardware => serialport::FlowControl::Hardware,
+        }
+    }
+}
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
 #[serde(tag = "type")]
 pub enum ConnectionType {
-    Usb { port: String, baud_rate: u32 },
-    Tcp { address: String, port: u16 },
+    Usb {
+        port: String,
+        baud_rate: u32,
+        #[serde(default)]
+        data_bits: Option<u8>,
+        #[serde(default)]
+        parity: Option<Parity>,
+        #[serde(default)]
+        stop_bits: Option<u8>,
+        #[serde(default)]
+        flow_control: Option<FlowControl>,
+        #[serde(default)]
+        read_timeout_ms: Option<u64>,
+    },
+    Tcp {
+        address: String,
+        port: u16,
+        #[serde(default)]
+        mode: TcpMode,
+        #[serde(default)]
+        accept_timeout: Option<u64>,
+        #[serde(default)]
+        reaccept: bool,
+    },
+    Udp {
+        address: String,
+        port: u16,
+        #[serde(default)]
+        local_port: Option<u16>,
+    },
+    #[cfg(unix)]
+    Unix { path: String },
+    Process {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        env: HashMap<String, String>,
+        #[serde(default)]
+        cwd: Option<String>,
+    },
+    #[cfg(unix)]
+    Pty {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        rows: Option<u16>,
+        #[serde(default)]
+        cols: Option<u16>,
+    },
+}
+
+impl ConnectionType {
+    fn validate(&self) -> Result<()> {
+        if let ConnectionType::Tcp {
+            mode: TcpMode::Connect,
+            accept_timeout,
+            reaccept,
+            ..
+        } = self
+            && (accept_timeout.is_some() || *reaccept)
+        {
+            bail!(
+                "accept_timeout and reaccept can only be used with a Tcp connection in listen mode"
+            );
+        }
+        if let ConnectionType::Usb {
+            data_bits,
+            stop_bits,
+            read_timeout_ms,
+            ..
+        } = self
+        {
+            if let Some(data_bits) = data_bits
+                && DataBits::try_from(*data_bits).is_err()
+            {
+                bail!("Usb data_bits must be between 5 and 8, got {data_bits}");
+            }
+            if let Some(stop_bits) = stop_bits
+                && StopBits::try_from(*stop_bits).is_err()
+            {
+                bail!("Usb stop_bits must be 1 or 2, got {stop_bits}");
+            }
+            if *read_timeout_ms == Some(0) {
+                bail!("Usb read_timeout_ms must be greater than 0");
+            }
+        }
+        Ok(())
+    }
+
+    // Datagrams already arrive as whole frames, everything else is read as lines by default
+    fn default_framing(&self) -> Framing {
+        match self {
+            ConnectionType::Udp { .. } => Framing::Raw,
+            _ => Framing::Delimiter(Vec::from(b"\n")),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", deny_unknown_fields)]
+enum RawFraming {
+    Delimiter {
+        #[serde(default)]
+        text: Option<String>,
+        #[serde(default)]
+        hex: Option<String>,
+    },
+    FixedLength {
+        length: usize,
+    },
+    IdleGap {
+        gap_ms: u64,
+    },
+    LengthPrefixed {
+        #[serde(default)]
+        sync: Option<String>,
+        #[serde(default)]
+        length_offset: Option<usize>,
+        length_width: usize,
+        #[serde(default)]
+        endian: Endian,
+        #[serde(default)]
+        length_includes_header: bool,
+        #[serde(default)]
+        checksum: Option<Checksum>,
+    },
+    Slip {
+        #[serde(default)]
+        encode_sends: bool,
+    },
+    Cobs {
+        #[serde(default)]
+        encode_sends: bool,
+    },
+    Raw {},
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Framing {
+    Delimiter(Vec<u8>),
+    FixedLength(usize),
+    IdleGap(Duration),
+    LengthPrefixed(Layout),
+    Slip { encode_sends: bool },
+    Cobs { encode_sends: bool },
+    Raw,
+}
+
+impl TryFrom<RawFraming> for Framing {
+    type Error = anyhow::Error;
+    fn try_from(value: RawFraming) -> Result<Self> {
+        Ok(match value {
+            RawFraming::Delimiter { text, hex } => {
+                let delimiter = match (text, hex) {
+                    (Some(text), None) => text.into_bytes(),
+                    (None, Some(hex)) => hex::decode(hex)?,
+                    _ => bail!("Delimiter framing needs exactly one of text or hex"),
+                };
+                if delimiter.is_empty() {
+                    bail!("Delimiter framing needs a delimiter of at least one byte");
+                }
+                Framing::Delimiter(delimiter)
+            }
+            RawFraming::FixedLength { length: 0 } => {
+                bail!("FixedLength framing needs a length greater than 0")
+            }
+            RawFraming::FixedLength { length } => Framing::FixedLength(length),
+            RawFraming::IdleGap { gap_ms: 0 } => {
+                bail!("IdleGap framing needs a gap_ms greater than 0")
+            }
+            RawFraming::IdleGap { gap_ms } => Framing::IdleGap(Duration::from_millis(gap_ms)),
+            RawFraming::LengthPrefixed {
+                sync,
+                length_offset,
+                length_width,
+                endian,
+                length_includes_header,
+                checksum,
+            } => {
+                let sync = sync.map(hex::decode).transpose()?.unwrap_or_default();
+                // The length field sits right after the sync word unless told otherwise
+                let length_offset = length_offset.unwrap_or(sync.len());
+                if !matches!(length_width, 1 | 2 | 4) {
+                    bail!("LengthPrefixed framing needs a length_width of 1, 2 or 4");
+                }
+                if length_offset < sync.len() {
+                    bail!(
+                        "LengthPrefixed framing can't have the length field inside the sync word"
+                    );
+                }
+                Framing::LengthPrefixed(Layout {
+                    sync,
+                    length_offset,
+                    length_width,
+                    endian,
+                    length_includes_header,
+                    checksum,
+                })
+            }
+            RawFraming::Slip { encode_sends } => Framing::Slip { encode_sends },
+            RawFraming::Cobs { encode_sends } => Framing::Cobs { encode_sends },
+            RawFraming::Raw {} => Framing::Raw,
+        })
+    }
 }
 
 #[derive(Deserialize)]
@@ -19,6 +273,10 @@ struct RawConfig {
     #[serde(default)]
     results_location: Option<String>,
     connection: ConnectionType,
+    #[serde(default)]
+    framing: Option<RawFraming>,
+    #[serde(default)]
+    variables: Definitions,
     scenarios: Vec<String>,
 }
 
@@ -27,6 +285,8 @@ pub struct Config {
     pub scenarios_location: PathBuf,
     pub results_location: PathBuf,
     pub connection: ConnectionType,
+    pub framing: Framing,
+    pub variables: Definitions,
     pub scenarios: Vec<PathBuf>,
 }
 
@@ -40,13 +300,27 @@ impl Config {
         // Process the fields from the raw struct into the final output
         let config_reader = io::BufReader::new(File::open(config_file_path)?);
         let parsed_raw_config: RawConfig = serde_json::from_reader(config_reader)?;
+        parsed_raw_config.connection.validate()?;
+        if let Some(name) = parsed_raw_config
+            .variables
+            .keys()
+            .find(|name| !variables::is_name(name.as_bytes()))
+        {
+            bail!("Variable name {name:?} can only use letters, digits and underscores");
+        }
         let temp_path = match parsed_raw_config.scenarios_location {
             Some(value) => PathBuf::from(value),
             None => PathBuf::from("."),
         };
+        let framing = match parsed_raw_config.framing {
+            Some(framing) => Framing::try_from(framing)?,
+            None => parsed_raw_config.connection.default_framing(),
+        };
         let processed_config = Config {
             scenarios_location: temp_path.clone(),
             connection: parsed_raw_config.connection,
+            framing,
+            variables: parsed_raw_config.variables,
             results_location: match parsed_raw_config.results_location {
                 Some(value) => PathBuf::from(value),
                 None => temp_path.clone(),
@@ -212,7 +486,12 @@ mod tests {
             connection: ConnectionType::Tcp {
                 address: String::from("test"),
                 port: 8080,
+                mode: TcpMode::Connect,
+                accept_timeout: None,
+                reaccept: false,
             },
+            framing: Framing::Delimiter(Vec::from(b"\n")),
+            variables: Definitions::new(),
             scenarios: vec![PathBuf::from("./scenario1"), PathBuf::from("./scenario2")],
         };
         assert_eq!(result, assert_config);
@@ -247,7 +526,12 @@ mod tests {
             connection: ConnectionType::Tcp {
                 address: String::from("test"),
                 port: 8080,
+                mode: TcpMode::Connect,
+                accept_timeout: None,
+                reaccept: false,
             },
+            framing: Framing::Delimiter(Vec::from(b"\n")),
+            variables: Definitions::new(),
             scenarios: vec![PathBuf::from("./scenario1"), PathBuf::from("./scenario2")],
         };
         assert_eq!(result, assert_config);
@@ -282,12 +566,85 @@ mod tests {
             connection: ConnectionType::Tcp {
                 address: String::from("test"),
                 port: 8080,
+                mode: TcpMode::Connect,
+                accept_timeout: None,
+                reaccept: false,
             },
+            framing: Framing::Delimiter(Vec::from(b"\n")),
+            variables: Definitions::new(),
             scenarios: vec![PathBuf::from("./scenario1"), PathBuf::from("./scenario2")],
         };
         assert_eq!(result, assert_config);
     }
 
+    #[test]
+    fn config_new_pass_tcp_listen_io() {
+        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let raw_json = r#"
+            {
+                "connection": {
+                    "type": "Tcp",
+                    "address": "0.0.0.0",
+                    "port": 9000,
+                    "mode": "listen",
+                    "accept_timeout": 30,
+                    "reaccept": true
+                },
+                "scenarios": [
+                    "scenario1"
+                ]
+            }
+            "#;
+        temp_file
+            .write_all(raw_json.as_bytes())
+            .expect("Failed to write to temp file");
+        let result = Config::new(temp_file.path().to_str().unwrap().to_string())
+            .expect("Somehow a valid struct wasn't created");
+        let assert_config = Config {
+            scenarios_location: PathBuf::from("."),
+            results_location: PathBuf::from("."),
+            connection: ConnectionType::Tcp {
+                address: String::from("0.0.0.0"),
+                port: 9000,
+                mode: TcpMode::Listen,
+                accept_timeout: Some(30),
+                reaccept: true,
+            },
+            framing: Framing::Delimiter(Vec::from(b"\n")),
+            variables: Definitions::new(),
+            scenarios: vec![PathBuf::from("./scenario1")],
+        };
+        assert_eq!(result, assert_config);
+    }
+
+    #[test]
+    fn config_new_fail_tcp_reaccept_in_connect_mode() {
+        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let raw_json = r#"
+            {
+                "connection": {
+                    "type": "Tcp",
+                    "address": "test",
+                    "port": 9000,
+                    "reaccept": true
+                },
+                "scenarios": [
+                    "scenario1"
+                ]
+            }
+            "#;
+        temp_file
+            .write_all(raw_json.as_bytes())
+            .expect("Failed to write to temp file");
+
+        let result = Config::new(temp_file.path().to_str().unwrap().to_string());
+
+        assert!(
+            result.is_err(),
+            "Somehow reaccept was allowed without listen mode"
+        );
+    }
+
     #[test]
     fn config_new_pass_usb_io() {
         let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
@@ -317,12 +674,383 @@ mod tests {
             connection: ConnectionType::Usb {
                 port: String::from("test"),
                 baud_rate: 115200,
+                data_bits: None,
+                parity: None,
+                stop_bits: None,
+                flow_control: None,
+                read_timeout_ms: None,
             },
+            framing: Framing::Delimiter(Vec::from(b"\n")),
+            variables: Definitions::new(),
             scenarios: vec![PathBuf::from("./scenario1"), PathBuf::from("./scenario2")],
         };
         assert_eq!(result, assert_config);
     }
 
+    #[test]
+    fn config_new_pass_usb_line_settings_io() {
+        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let raw_json = r#"
+            {
+                "connection": {
+                    "type": "Usb",
+                    "port": "test",
+                    "baud_rate": 9600,
+                    "data_bits": 7,
+                    "parity": "even",
+                    "stop_bits": 1,
+                    "flow_control": "hardware",
+                    "read_timeout_ms": 250
+                },
+                "scenarios": [
+                    "scenario1"
+                ]
+            }
+            "#;
+        temp_file
+            .write_all(raw_json.as_bytes())
+            .expect("Failed to write to temp file");
+        let result = Config::new(temp_file.path().to_str().unwrap().to_string())
+            .expect("Somehow a valid struct wasn't created");
+        let assert_config = Config {
+            scenarios_location: PathBuf::from("."),
+            results_location: PathBuf::from("."),
+            connection: ConnectionType::Usb {
+                port: String::from("test"),
+                baud_rate: 9600,
+                data_bits: Some(7),
+                parity: Some(Parity::Even),
+                stop_bits: Some(1),
+                flow_control: Some(FlowControl::Hardware),
+                read_timeout_ms: Some(250),
+            },
+            framing: Framing::Delimiter(Vec::from(b"\n")),
+            variables: Definitions::new(),
+            scenarios: vec![PathBuf::from("./scenario1")],
+        };
+        assert_eq!(result, assert_config);
+    }
+
+    #[test]
+    fn config_new_fail_usb_line_settings() {
+        for connection in [
+            r#""data_bits": 9"#,
+            r#""stop_bits": 3"#,
+            r#""parity": "mark""#,
+            r#""flow_control": "xon""#,
+            r#""read_timeout_ms": 0"#,
+        ] {
+            let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+            let raw_json = format!(
+                r#"{{
+                    "connection": {{ "type": "Usb", "port": "test", "baud_rate": 9600, {connection} }},
+                    "scenarios": ["scenario1"]
+                }}"#
+            );
+            temp_file
+                .write_all(raw_json.as_bytes())
+                .expect("Failed to write to temp file");
+
+            let result = Config::new(temp_file.path().to_str().unwrap().to_string());
+
+            assert!(result.is_err(), "Somehow {connection} was accepted");
+        }
+    }
+
+    #[test]
+    fn config_new_pass_udp_io() {
+        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let raw_json = r#"
+            {
+                "connection": {
+                    "type": "Udp",
+                    "address": "test",
+                    "port": 5000,
+                    "local_port": 5001
+                },
+                "scenarios": [
+                    "scenario1"
+                ]
+            }
+            "#;
+        temp_file
+            .write_all(raw_json.as_bytes())
+            .expect("Failed to write to temp file");
+        let result = Config::new(temp_file.path().to_str().unwrap().to_string())
+            .expect("Somehow a valid struct wasn't created");
+        let assert_config = Config {
+            scenarios_location: PathBuf::from("."),
+            results_location: PathBuf::from("."),
+            connection: ConnectionType::Udp {
+                address: String::from("test"),
+                port: 5000,
+                local_port: Some(5001),
+            },
+            framing: Framing::Raw,
+            variables: Definitions::new(),
+            scenarios: vec![PathBuf::from("./scenario1")],
+        };
+        assert_eq!(result, assert_config);
+    }
+
+    #[test]
+    fn config_new_pass_variables() {
+        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let raw_json = r#"
+            {
+                "connection": {
+                    "type": "Udp",
+                    "address": "test",
+                    "port": 5000
+                },
+                "variables": {
+                    "model": "X200",
+                    "header_2": "aa55"
+                },
+                "scenarios": []
+            }
+            "#;
+        temp_file
+            .write_all(raw_json.as_bytes())
+            .expect("Failed to write to temp file");
+        let result = Config::new(temp_file.path().to_str().unwrap().to_string())
+            .expect("Somehow a valid struct wasn't created");
+
+        assert_eq!(
+            result.variables,
+            Definitions::from([
+                (String::from("model"), String::from("X200")),
+                (String::from("header_2"), String::from("aa55")),
+            ])
+        );
+    }
+
+    #[test]
+    fn config_new_fail_variable_name() {
+        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let raw_json = r#"
+            {
+                "connection": {
+                    "type": "Udp",
+                    "address": "test",
+                    "port": 5000
+                },
+                "variables": { "device-model": "X200" },
+                "scenarios": []
+            }
+            "#;
+        temp_file
+            .write_all(raw_json.as_bytes())
+            .expect("Failed to write to temp file");
+        let result = Config::new(temp_file.path().to_str().unwrap().to_string());
+
+        assert!(
+            result.is_err(),
+            "Somehow a variable name with a dash was accepted"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn config_new_pass_unix_io() {
+        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let raw_json = r#"
+            {
+                "connection": {
+                    "type": "Unix",
+                    "path": "/tmp/device.sock"
+                },
+                "scenarios": [
+                    "scenario1"
+                ]
+            }
+            "#;
+        temp_file
+            .write_all(raw_json.as_bytes())
+            .expect("Failed to write to temp file");
+        let result = Config::new(temp_file.path().to_str().unwrap().to_string())
+            .expect("Somehow a valid struct wasn't created");
+        let assert_config = Config {
+            scenarios_location: PathBuf::from("."),
+            results_location: PathBuf::from("."),
+            connection: ConnectionType::Unix {
+                path: String::from("/tmp/device.sock"),
+            },
+            framing: Framing::Delimiter(Vec::from(b"\n")),
+            variables: Definitions::new(),
+            scenarios: vec![PathBuf::from("./scenario1")],
+        };
+        assert_eq!(result, assert_config);
+    }
+
+    #[test]
+    fn config_new_pass_process_io() {
+        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let raw_json = r#"
+            {
+                "connection": {
+                    "type": "Process",
+                    "program": "./simulator",
+                    "args": ["--verbose"],
+                    "env": {"DEVICE_ID": "42"}
+                },
+                "scenarios": [
+                    "scenario1"
+                ]
+            }
+            "#;
+        temp_file
+            .write_all(raw_json.as_bytes())
+            .expect("Failed to write to temp file");
+        let result = Config::new(temp_file.path().to_str().unwrap().to_string())
+            .expect("Somehow a valid struct wasn't created");
+        let assert_config = Config {
+            scenarios_location: PathBuf::from("."),
+            results_location: PathBuf::from("."),
+            connection: ConnectionType::Process {
+                program: String::from("./simulator"),
+                args: vec![String::from("--verbose")],
+                env: HashMap::from([(String::from("DEVICE_ID"), String::from("42"))]),
+                cwd: None,
+            },
+            framing: Framing::Delimiter(Vec::from(b"\n")),
+            variables: Definitions::new(),
+            scenarios: vec![PathBuf::from("./scenario1")],
+        };
+        assert_eq!(result, assert_config);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn config_new_pass_pty_io() {
+        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let raw_json = r#"
+            {
+                "connection": {
+                    "type": "Pty",
+                    "program": "bash",
+                    "args": ["--norc"],
+                    "rows": 50
+                },
+                "scenarios": [
+                    "scenario1"
+                ]
+            }
+            "#;
+        temp_file
+            .write_all(raw_json.as_bytes())
+            .expect("Failed to write to temp file");
+        let result = Config::new(temp_file.path().to_str().unwrap().to_string())
+            .expect("Somehow a valid struct wasn't created");
+        let assert_config = Config {
+            scenarios_location: PathBuf::from("."),
+            results_location: PathBuf::from("."),
+            connection: ConnectionType::Pty {
+                program: String::from("bash"),
+                args: vec![String::from("--norc")],
+                rows: Some(50),
+                cols: None,
+            },
+            framing: Framing::Delimiter(Vec::from(b"\n")),
+            variables: Definitions::new(),
+            scenarios: vec![PathBuf::from("./scenario1")],
+        };
+        assert_eq!(result, assert_config);
+    }
+
+    #[test]
+    fn config_new_pass_framing() {
+        for (framing, expected) in [
+            (
+                r#"{ "type": "Delimiter", "text": "\r\n" }"#,
+                Framing::Delimiter(Vec::from(b"\r\n")),
+            ),
+            (
+                r#"{ "type": "Delimiter", "hex": "c0" }"#,
+                Framing::Delimiter(vec![0xc0]),
+            ),
+            (
+                r#"{ "type": "FixedLength", "length": 16 }"#,
+                Framing::FixedLength(16),
+            ),
+            (
+                r#"{ "type": "IdleGap", "gap_ms": 50 }"#,
+                Framing::IdleGap(Duration::from_millis(50)),
+            ),
+            (
+                r#"{ "type": "LengthPrefixed", "sync": "aa55", "length_width": 2, "endian": "little", "checksum": "crc16_ccitt" }"#,
+                Framing::LengthPrefixed(Layout {
+                    sync: vec![0xaa, 0x55],
+                    length_offset: 2,
+                    length_width: 2,
+                    endian: Endian::Little,
+                    length_includes_header: false,
+                    checksum: Some(Checksum::Crc16Ccitt),
+                }),
+            ),
+            (
+                r#"{ "type": "Slip", "encode_sends": true }"#,
+                Framing::Slip { encode_sends: true },
+            ),
+            (
+                r#"{ "type": "Cobs" }"#,
+                Framing::Cobs {
+                    encode_sends: false,
+                },
+            ),
+            (r#"{ "type": "Raw" }"#, Framing::Raw),
+        ] {
+            let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+            let raw_json = format!(
+                r#"{{
+                    "connection": {{ "type": "Tcp", "address": "test", "port": 9000 }},
+                    "framing": {framing},
+                    "scenarios": ["scenario1"]
+                }}"#
+            );
+            temp_file
+                .write_all(raw_json.as_bytes())
+                .expect("Failed to write to temp file");
+
+            let result = Config::new(temp_file.path().to_str().unwrap().to_string())
+                .expect("Somehow a valid struct wasn't created");
+
+            assert_eq!(result.framing, expected);
+        }
+    }
+
+    #[test]
+    fn config_new_fail_framing() {
+        for framing in [
+            r#"{ "type": "Delimiter" }"#,
+            r#"{ "type": "Delimiter", "text": "\n", "hex": "0a" }"#,
+            r#"{ "type": "Delimiter", "text": "" }"#,
+            r#"{ "type": "Delimiter", "hex": "zz" }"#,
+            r#"{ "type": "FixedLength", "length": 0 }"#,
+            r#"{ "type": "IdleGap", "gap_ms": 0 }"#,
+            r#"{ "type": "Raw", "length": 4 }"#,
+            r#"{ "type": "LengthPrefixed", "length_width": 3 }"#,
+            r#"{ "type": "LengthPrefixed", "sync": "aa55", "length_offset": 1, "length_width": 1 }"#,
+            r#"{ "type": "LengthPrefixed", "length_width": 1, "checksum": "md5" }"#,
+        ] {
+            let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+            let raw_json = format!(
+                r#"{{
+                    "connection": {{ "type": "Tcp", "address": "test", "port": 9000 }},
+                    "framing": {framing},
+                    "scenarios": ["scenario1"]
+                }}"#
+            );
+            temp_file
+                .write_all(raw_json.as_bytes())
+                .expect("Failed to write to temp file");
+
+            let result = Config::new(temp_file.path().to_str().unwrap().to_string());
+
+            assert!(result.is_err(), "Somehow {framing} was accepted");
+        }
+    }
+
     #[test]
     fn config_new_pass_without_any_location() {
         let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
@@ -351,7 +1079,12 @@ mod tests {
             connection: ConnectionType::Tcp {
                 address: String::from("test"),
                 port: 8080,
+                mode: TcpMode::Connect,
+                accept_timeout: None,
+                reaccept: false,
             },
+            framing: Framing::Delimiter(Vec::from(b"\n")),
+            variables: Definitions::new(),
             scenarios: vec![PathBuf::from("./scenario1"), PathBuf::from("./scenario2")],
         };
         assert_eq!(result, assert_config);
@@ -0,0 +1,106 @@
+use anyhow::{Result, anyhow, bail};
+use serde::Deserialize;
+use std::time::Duration;
+
+// Plain numbers are seconds, which is what scenario files have always used
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum RawDuration {
+    Seconds(f64),
+    Text(String),
+}
+
+impl TryFrom<RawDuration> for Duration {
+    type Error = anyhow::Error;
+    fn try_from(value: RawDuration) -> Result<Self> {
+        match value {
+            RawDuration::Seconds(seconds) => from_secs(seconds),
+            RawDuration::Text(text) => parse(&text),
+        }
+    }
+}
+
+fn from_secs(seconds: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| anyhow!("{seconds} is not a valid number of seconds"))
+}
+
+pub fn parse(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    let split = text
+        .find(|character: char| !(character.is_ascii_digit() || character == '.'))
+        .unwrap_or(text.len());
+    let (value, unit) = text.split_at(split);
+    let Ok(value) = value.parse::<f64>() else {
+        bail!("{text:?} is not a duration like 250ms, 1.5s, 2m or 8h");
+    };
+    let scale = match unit.trim() {
+        "ms" => 0.001,
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => bail!("{text:?} needs a unit of ms, s, m or h"),
+    };
+    from_secs(value * scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parse_pass() {
+        assert_eq!(
+            parse("250ms").expect("Failed to parse"),
+            Duration::from_millis(250)
+        );
+        assert_eq!(
+            parse("1.5s").expect("Failed to parse"),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(
+            parse(" 2 m ").expect("Failed to parse"),
+            Duration::from_secs(120)
+        );
+        assert_eq!(
+            parse("8h").expect("Failed to parse"),
+            Duration::from_secs(8 * 3600)
+        );
+        assert_eq!(parse("0ms").expect("Failed to parse"), Duration::ZERO);
+    }
+
+    #[test]
+    fn parse_fail() {
+        for text in ["", "ms", "250", "1.5d", "-1s", "1..5s", "fast"] {
+            assert!(parse(text).is_err(), "Somehow parsed {text:?}");
+        }
+    }
+
+    #[test]
+    fn raw_duration_pass() {
+        let durations: Vec<RawDuration> =
+            serde_json::from_str(r#"[5, 0.05, "50ms"]"#).expect("Failed to deserialize");
+        let durations: Vec<Duration> = durations
+            .into_iter()
+            .map(|duration| Duration::try_from(duration).expect("Failed to convert"))
+            .collect();
+
+        assert_eq!(
+            durations,
+            vec![
+                Duration::from_secs(5),
+                Duration::from_millis(50),
+                Duration::from_millis(50)
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_duration_fail_negative() {
+        assert!(
+            Duration::try_from(RawDuration::Seconds(-1.0)).is_err(),
+            "Somehow made a negative duration"
+        );
+    }
+}
@@ -0,0 +1,456 @@
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Clone)]
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+// Dotted keys with array indexes, e.g. "data.readings[0].value", an empty path is the whole response
+#[derive(Debug, PartialEq, Clone)]
+pub struct JsonPath {
+    text: String,
+    steps: Vec<Step>,
+}
+
+impl JsonPath {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut steps = Vec::new();
+        for (position, part) in text.split('.').enumerate() {
+            if part.is_empty() {
+                if text.is_empty() {
+                    break;
+                }
+                bail!("{text:?} has an empty key");
+            }
+            let (key, mut indexes) = part.split_at(part.find('[').unwrap_or(part.len()));
+            if key.contains(']') {
+                bail!("{text:?} has a ] without a [ in front of it");
+            }
+            // A path can start with an index when the response is an array
+            if !key.is_empty() {
+                steps.push(Step::Key(String::from(key)));
+            } else if position > 0 {
+                bail!("{text:?} has an index without a key in front of it");
+            }
+            while !indexes.is_empty() {
+                let Some((index, rest)) = indexes
+                    .strip_prefix('[')
+                    .and_then(|indexes| indexes.split_once(']'))
+                else {
+                    bail!("{text:?} has an unclosed or misplaced [");
+                };
+                let Ok(index) = index.parse::<usize>() else {
+                    bail!("{text:?} has {index:?} where an array index was expected");
+                };
+                steps.push(Step::Index(index));
+                indexes = rest;
+            }
+        }
+        Ok(JsonPath {
+            text: String::from(text),
+            steps,
+        })
+    }
+
+    pub fn find<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.steps.iter().try_fold(value, |value, step| match step {
+            Step::Key(key) => value.get(key),
+            Step::Index(index) => value.get(index),
+        })
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.text.is_empty() {
+            true => write!(f, "(root)"),
+            false => write!(f, "{}", self.text),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum JsonType {
+    Null,
+    Boolean,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+impl JsonType {
+    fn of(value: &Value) -> Self {
+        match value {
+            Value::Null => JsonType::Null,
+            Value::Bool(_) => JsonType::Boolean,
+            Value::Number(_) => JsonType::Number,
+            Value::String(_) => JsonType::String,
+            Value::Array(_) => JsonType::Array,
+            Value::Object(_) => JsonType::Object,
+        }
+    }
+}
+
+impl fmt::Display for JsonType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            JsonType::Null => "null",
+            JsonType::Boolean => "a boolean",
+            JsonType::Number => "a number",
+            JsonType::String => "a string",
+            JsonType::Array => "an array",
+            JsonType::Object => "an object",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RawJsonAssertion {
+    #[serde(default)]
+    equals: Option<Value>,
+    #[serde(default, rename = "type")]
+    kind: Option<JsonType>,
+    #[serde(default)]
+    gt: Option<f64>,
+    #[serde(default)]
+    gte: Option<f64>,
+    #[serde(default)]
+    lt: Option<f64>,
+    #[serde(default)]
+    lte: Option<f64>,
+    #[serde(default)]
+    present: Option<bool>,
+    #[serde(default)]
+    length: Option<usize>,
+}
+
+impl RawJsonAssertion {
+    // Strings inside equals, so placeholders can be filled in like anywhere else
+    pub fn strings_mut(&mut self) -> Vec<&mut String> {
+        fn collect<'a>(value: &'a mut Value, strings: &mut Vec<&'a mut String>) {
+            match value {
+                Value::String(string) => strings.push(string),
+                Value::Array(values) => values.iter_mut().for_each(|value| collect(value, strings)),
+                Value::Object(map) => map.values_mut().for_each(|value| collect(value, strings)),
+                _ => {}
+            }
+        }
+        let mut strings = Vec::new();
+        if let Some(equals) = &mut self.equals {
+            collect(equals, &mut strings);
+        }
+        strings
+    }
+
+    pub fn into_assertion(self, path: &str) -> Result<JsonAssertion> {
+        let path = JsonPath::parse(path)?;
+        let checks_value = self.equals.is_some()
+            || self.kind.is_some()
+            || self.gt.is_some()
+            || self.gte.is_some()
+            || self.lt.is_some()
+            || self.lte.is_some()
+            || self.length.is_some();
+        match self.present {
+            None if !checks_value => {
+                bail!("{path} needs one of equals, type, gt, gte, lt, lte, present or length")
+            }
+            Some(false) if checks_value => bail!("{path} can't be checked if it must be absent"),
+            _ => {}
+        }
+        Ok(JsonAssertion {
+            path,
+            equals: self.equals,
+            kind: self.kind,
+            gt: self.gt,
+            gte: self.gte,
+            lt: self.lt,
+            lte: self.lte,
+            present: self.present.unwrap_or(true),
+            length: self.length,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct JsonAssertion {
+    pub path: JsonPath,
+    equals: Option<Value>,
+    kind: Option<JsonType>,
+    gt: Option<f64>,
+    gte: Option<f64>,
+    lt: Option<f64>,
+    lte: Option<f64>,
+    present: bool,
+    length: Option<usize>,
+}
+
+// Numbers compare by value so 1 and 1.0 are the same, objects already ignore key order
+fn same(expected: &Value, actual: &Value) -> bool {
+    match (expected, actual) {
+        (Value::Number(expected), Value::Number(actual)) => expected.as_f64() == actual.as_f64(),
+        (Value::Array(expected), Value::Array(actual)) => {
+            expected.len() == actual.len()
+                && expected
+                    .iter()
+                    .zip(actual)
+                    .all(|(expected, actual)| same(expected, actual))
+        }
+        (Value::Object(expected), Value::Object(actual)) => {
+            expected.len() == actual.len()
+                && expected.iter().all(|(key, expected)| {
+                    actual.get(key).is_some_and(|actual| same(expected, actual))
+                })
+        }
+        _ => expected == actual,
+    }
+}
+
+fn length(value: &Value) -> Option<usize> {
+    match value {
+        Value::String(string) => Some(string.chars().count()),
+        Value::Array(values) => Some(values.len()),
+        Value::Object(map) => Some(map.len()),
+        _ => None,
+    }
+}
+
+impl JsonAssertion {
+    // Describes what went wrong in terms of the path, None when the response holds up
+    pub fn check(&self, response: &Value) -> Option<String> {
+        let path = &self.path;
+        let Some(value) = path.find(response) else {
+            return match self.present {
+                true => Some(format!("{path}: expected to be present, but it is missing")),
+                false => None,
+            };
+        };
+        if !self.present {
+            return Some(format!("{path}: expected to be absent, got {value}"));
+        }
+        if let Some(equals) = &self.equals
+            && !same(equals, value)
+        {
+            return Some(format!("{path}: expected {equals}, got {value}"));
+        }
+        if let Some(kind) = self.kind
+            && JsonType::of(value) != kind
+        {
+            return Some(format!("{path}: expected {kind}, got {value}"));
+        }
+        let comparisons = [
+            (self.gt, "more than", f64::gt as fn(&f64, &f64) -> bool),
+            (self.gte, "at least", f64::ge),
+            (self.lt, "less than", f64::lt),
+            (self.lte, "at most", f64::le),
+        ];
+        for (bound, wording, compare) in comparisons {
+            let Some(bound) = bound else {
+                continue;
+            };
+            match value.as_f64() {
+                Some(number) if compare(&number, &bound) => {}
+                _ => return Some(format!("{path}: expected {wording} {bound}, got {value}")),
+            }
+        }
+        if let Some(expected) = self.length {
+            match length(value) {
+                Some(actual) if actual == expected => {}
+                Some(actual) => {
+                    return Some(format!(
+                        "{path}: expected a length of {expected}, got {actual}"
+                    ));
+                }
+                None => {
+                    return Some(format!(
+                        "{path}: expected a length of {expected}, got {value}"
+                    ));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for JsonAssertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut checks = Vec::new();
+        if !self.present {
+            checks.push(String::from("absent"));
+        }
+        if let Some(equals) = &self.equals {
+            checks.push(format!("== {equals}"));
+        }
+        if let Some(kind) = self.kind {
+            checks.push(format!("is {kind}"));
+        }
+        for (bound, operator) in [
+            (self.gt, ">"),
+            (self.gte, ">="),
+            (self.lt, "<"),
+            (self.lte, "<="),
+        ] {
+            if let Some(bound) = bound {
+                checks.push(format!("{operator} {bound}"));
+            }
+        }
+        if let Some(length) = self.length {
+            checks.push(format!("length {length}"));
+        }
+        if checks.is_empty() {
+            checks.push(String::from("present"));
+        }
+        write!(f, "{} {}", self.path, checks.join(", "))
+    }
+}
+
+// Frames that aren't JSON at all can't be checked, so they're left for other expectations
+pub fn parse_response(data: &[u8]) -> Option<Value> {
+    serde_json::from_slice(data).ok()
+}
+
+pub fn mismatches(assertions: &[JsonAssertion], response: &Value) -> Vec<String> {
+    assertions
+        .iter()
+        .filter_map(|assertion| assertion.check(response))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    fn assertion(path: &str, raw: Value) -> JsonAssertion {
+        serde_json::from_value::<RawJsonAssertion>(raw)
+            .expect("Failed to deserialize")
+            .into_assertion(path)
+            .expect("Failed to make assertion")
+    }
+
+    #[test]
+    fn json_path_find_pass() {
+        let response = json!({ "data": { "readings": [{ "value": 1 }, { "value": 2 }] } });
+
+        assert_eq!(
+            JsonPath::parse("data.readings[1].value")
+                .expect("Failed to parse")
+                .find(&response),
+            Some(&json!(2))
+        );
+        assert_eq!(
+            JsonPath::parse("")
+                .expect("Failed to parse")
+                .find(&response),
+            Some(&response)
+        );
+        assert_eq!(
+            JsonPath::parse("[0][1]")
+                .expect("Failed to parse")
+                .find(&json!([[1, 2]])),
+            Some(&json!(2))
+        );
+        assert_eq!(
+            JsonPath::parse("data.readings[2]")
+                .expect("Failed to parse")
+                .find(&response),
+            None
+        );
+    }
+
+    #[test]
+    fn json_path_parse_fail() {
+        for path in [
+            "data..value",
+            "data.",
+            "data[x]",
+            "data[1",
+            "data]",
+            "a.[0]",
+        ] {
+            assert!(JsonPath::parse(path).is_err(), "Somehow parsed {path:?}");
+        }
+    }
+
+    #[test]
+    fn json_assertion_check_pass() {
+        let response: Value = serde_json::from_str(
+            r#"{ "temp": 21.5, "mode": "idle", "tags": ["a", "b"], "config": { "b": 2.0, "a": 1 } }"#,
+        )
+        .expect("Failed to parse");
+
+        let assertions = [
+            assertion("temp", json!({ "type": "number", "gte": 20, "lt": 30 })),
+            assertion("mode", json!({ "equals": "idle" })),
+            assertion("tags", json!({ "length": 2 })),
+            assertion("config", json!({ "equals": { "a": 1.0, "b": 2 } })),
+            assertion("error", json!({ "present": false })),
+            assertion("tags[0]", json!({ "present": true })),
+        ];
+
+        assert_eq!(mismatches(&assertions, &response), Vec::<String>::new());
+    }
+
+    #[test]
+    fn json_assertion_check_fail() {
+        let response = json!({ "temp": 35, "mode": "busy", "tags": [], "error": "overheat" });
+
+        let assertions = [
+            assertion("temp", json!({ "lt": 30 })),
+            assertion("mode", json!({ "equals": "idle" })),
+            assertion("tags", json!({ "length": 2 })),
+            assertion("error", json!({ "present": false })),
+            assertion("serial", json!({ "type": "string" })),
+            assertion("mode", json!({ "type": "number" })),
+        ];
+
+        assert_eq!(
+            mismatches(&assertions, &response),
+            vec![
+                String::from("temp: expected less than 30, got 35"),
+                String::from("mode: expected \"idle\", got \"busy\""),
+                String::from("tags: expected a length of 2, got 0"),
+                String::from("error: expected to be absent, got \"overheat\""),
+                String::from("serial: expected to be present, but it is missing"),
+                String::from("mode: expected a number, got \"busy\""),
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_json_assertion_fail() {
+        for raw in [json!({}), json!({ "present": false, "equals": 1 })] {
+            let raw: RawJsonAssertion = serde_json::from_value(raw).expect("Failed to deserialize");
+
+            assert!(
+                raw.into_assertion("a").is_err(),
+                "Somehow made an assertion"
+            );
+        }
+        assert!(
+            serde_json::from_value::<RawJsonAssertion>(json!({ "equal": 1 })).is_err(),
+            "Somehow deserialized an unknown check"
+        );
+    }
+
+    #[test]
+    fn json_assertion_display_pass() {
+        assert_eq!(
+            assertion("temp", json!({ "type": "number", "gt": 20 })).to_string(),
+            "temp is a number, > 20"
+        );
+        assert_eq!(
+            assertion("", json!({ "length": 3 })).to_string(),
+            "(root) length 3"
+        );
+    }
+}
@@ -0,0 +1,315 @@
+use anyhow::{Result, bail};
+use regex::bytes::Regex;
+use std::fmt;
+
+use super::json::{self, JsonAssertion};
+use super::measurement::Measurement;
+use super::variables::Capture;
+
+// Frames are raw bytes, so patterns are matched as bytes and work on binary responses too
+#[derive(Clone)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Self> {
+        Ok(Pattern(Regex::new(pattern)?))
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    pub fn is_match(&self, data: &[u8]) -> bool {
+        self.0.is_match(data)
+    }
+
+    pub fn has_group(&self, group: &str) -> bool {
+        self.0.capture_names().flatten().any(|name| name == group)
+    }
+
+    pub fn capture(&self, data: &[u8], group: &str) -> Option<Vec<u8>> {
+        let captures = self.0.captures(data)?;
+        Some(Vec::from(captures.name(group)?.as_bytes()))
+    }
+}
+
+// Compiled regexes can't be compared, the source they came from can
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pattern({:?})", self.as_str())
+    }
+}
+
+// Expected bytes where any bit cleared in the mask is ignored, text and plain hex keep every bit
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct MaskedBytes {
+    value: Vec<u8>,
+    mask: Vec<u8>,
+}
+
+impl MaskedBytes {
+    pub fn exact(value: Vec<u8>) -> Self {
+        let mask = vec![0xff; value.len()];
+        MaskedBytes { value, mask }
+    }
+
+    // Whitespace is ignored and ? stands in for a nibble that can be anything, e.g. "AA 55 ?? 0? 01"
+    pub fn parse_hex(text: &str) -> Result<Self> {
+        let nibbles: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+        if !nibbles.len().is_multiple_of(2) {
+            bail!("{text:?} has an odd number of hex digits");
+        }
+        let mut value = Vec::with_capacity(nibbles.len() / 2);
+        let mut mask = Vec::with_capacity(nibbles.len() / 2);
+        for pair in nibbles.chunks(2) {
+            let (mut byte, mut byte_mask) = (0, 0);
+            for nibble in pair {
+                byte <<= 4;
+                byte_mask <<= 4;
+                if *nibble != '?' {
+                    let Some(digit) = nibble.to_digit(16) else {
+                        bail!("{text:?} has {nibble:?} where a hex digit or ? was expected");
+                    };
+                    byte |= digit as u8;
+                    byte_mask |= 0x0f;
+                }
+            }
+            value.push(byte);
+            mask.push(byte_mask);
+        }
+        Ok(MaskedBytes { value, mask })
+    }
+
+    // Narrows down what's compared, on top of any wildcards already in the value
+    pub fn with_mask(self, mask: Vec<u8>) -> Result<Self> {
+        if mask.len() != self.value.len() {
+            bail!(
+                "mask is {} bytes but the value is {} bytes",
+                mask.len(),
+                self.value.len()
+            );
+        }
+        let mask: Vec<u8> = self.mask.iter().zip(mask).map(|(a, b)| a & b).collect();
+        let value = self.value.iter().zip(&mask).map(|(a, b)| a & b).collect();
+        Ok(MaskedBytes { value, mask })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.value.is_empty()
+    }
+
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    pub fn is_prefix_of(&self, data: &[u8]) -> bool {
+        data.len() >= self.value.len()
+            && self
+                .value
+                .iter()
+                .zip(&self.mask)
+                .zip(data)
+                .all(|((value, mask), byte)| byte & mask == *value)
+    }
+
+    pub fn matches(&self, data: &[u8]) -> bool {
+        data.len() == self.value.len() && self.is_prefix_of(data)
+    }
+
+    pub fn to_hex(&self) -> String {
+        let mut hex = String::with_capacity(self.value.len() * 2);
+        for (value, mask) in self.value.iter().zip(&self.mask) {
+            for shift in [4, 0] {
+                match (mask >> shift) & 0x0f {
+                    0x0f => hex.push_str(&format!("{:x}", (value >> shift) & 0x0f)),
+                    0x00 => hex.push('?'),
+                    // Only reachable through a mask that splits a nibble
+                    _ => hex.push('*'),
+                }
+            }
+        }
+        hex
+    }
+}
+
+// A response a command waits for, picked out by its prefix, else its pattern, else by being JSON at all
+#[derive(Debug, PartialEq, Clone)]
+pub struct Expectation {
+    pub prefix: MaskedBytes,
+    pub exact: MaskedBytes,
+    pub regex: Option<Pattern>,
+    pub json: Vec<JsonAssertion>,
+    pub capture: Vec<Capture>,
+    pub measure: Vec<Measurement>,
+}
+
+impl Expectation {
+    pub fn picks_out(&self, data: &[u8]) -> bool {
+        if !self.prefix.is_empty() {
+            return self.prefix.is_prefix_of(data);
+        }
+        match &self.regex {
+            Some(pattern) => pattern.is_match(data),
+            None => !self.json.is_empty() && json::parse_response(data).is_some(),
+        }
+    }
+
+    pub fn matches(&self, data: &[u8]) -> bool {
+        self.picks_out(data)
+            && (self.exact.is_empty() || self.exact.matches(data))
+            && self
+                .regex
+                .as_ref()
+                .is_none_or(|pattern| pattern.is_match(data))
+            && self.json_mismatches(data).is_empty()
+    }
+
+    // One line per failed assertion, or a single line when the response isn't JSON
+    pub fn json_mismatches(&self, data: &[u8]) -> Vec<String> {
+        if self.json.is_empty() {
+            return Vec::new();
+        }
+        match json::parse_response(data) {
+            Some(response) => json::mismatches(&self.json, &response),
+            None => vec![String::from("response is not valid JSON")],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn pattern_new_fail_invalid() {
+        assert!(
+            Pattern::new("OK (").is_err(),
+            "Somehow compiled an unbalanced group"
+        );
+    }
+
+    #[test]
+    fn pattern_is_match_pass() {
+        let pattern = Pattern::new(r"^SN:[0-9A-F]{8} T=\d+$").expect("Failed to compile pattern");
+
+        assert!(pattern.is_match(b"SN:00C0FFEE T=1718"));
+        assert!(!pattern.is_match(b"SN:nothex T=1718"));
+    }
+
+    #[test]
+    fn pattern_is_match_binary_pass() {
+        let pattern = Pattern::new(r"(?-u)^\xAA\x55.\x01").expect("Failed to compile pattern");
+
+        assert!(pattern.is_match(&[0xaa, 0x55, 0xff, 0x01]));
+        assert!(!pattern.is_match(&[0xaa, 0x55, 0xff, 0x02]));
+    }
+
+    #[test]
+    fn pattern_capture_pass() {
+        let pattern = Pattern::new(r"^ID:(?<id>[0-9A-F]+)(?: (?<rev>r\d))?$")
+            .expect("Failed to compile pattern");
+
+        assert!(pattern.has_group("id"));
+        assert!(!pattern.has_group("serial"));
+        assert_eq!(
+            pattern.capture(b"ID:00C0FFEE", "id"),
+            Some(Vec::from("00C0FFEE"))
+        );
+        assert_eq!(pattern.capture(b"ID:00C0FFEE", "rev"), None);
+        assert_eq!(pattern.capture(b"nothing here", "id"), None);
+    }
+
+    #[test]
+    fn masked_bytes_parse_hex_pass() {
+        let masked = MaskedBytes::parse_hex("AA 55 ?? 0? 01").expect("Failed to parse");
+
+        assert!(masked.matches(&[0xaa, 0x55, 0x17, 0x03, 0x01]));
+        assert!(masked.matches(&[0xaa, 0x55, 0xff, 0x0f, 0x01]));
+        assert!(!masked.matches(&[0xaa, 0x55, 0x17, 0x13, 0x01]));
+        assert!(!masked.matches(&[0xaa, 0x55, 0x17, 0x03, 0x01, 0x00]));
+        assert!(masked.is_prefix_of(&[0xaa, 0x55, 0x17, 0x03, 0x01, 0x00]));
+        assert_eq!(masked.to_hex(), "aa55??0?01");
+    }
+
+    #[test]
+    fn masked_bytes_parse_hex_fail() {
+        for text in ["AA5", "AG", "AA 5 5 ?"] {
+            assert!(
+                MaskedBytes::parse_hex(text).is_err(),
+                "Somehow parsed {text:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn masked_bytes_with_mask_pass() {
+        let masked = MaskedBytes::parse_hex("AA5F??")
+            .and_then(|masked| masked.with_mask(vec![0xff, 0xf0, 0xff]))
+            .expect("Failed to mask");
+
+        assert!(masked.matches(&[0xaa, 0x53, 0x99]));
+        assert!(!masked.matches(&[0xab, 0x53, 0x99]));
+        assert_eq!(masked.to_hex(), "aa5???");
+        assert!(
+            MaskedBytes::exact(vec![0x01])
+                .with_mask(vec![0xff, 0xff])
+                .is_err(),
+            "Somehow masked with the wrong length"
+        );
+    }
+
+    #[test]
+    fn expectation_matches_pass() {
+        let exact = Expectation {
+            prefix: MaskedBytes::exact(Vec::from("Hello")),
+            exact: MaskedBytes::exact(Vec::from("Hello World!")),
+            regex: None,
+            json: Vec::new(),
+            capture: Vec::new(),
+            measure: Vec::new(),
+        };
+        let pattern = Expectation {
+            prefix: MaskedBytes::default(),
+            exact: MaskedBytes::default(),
+            regex: Some(Pattern::new(r"^T=\d+$").expect("Failed to compile pattern")),
+            json: Vec::new(),
+            capture: Vec::new(),
+            measure: Vec::new(),
+        };
+        let binary = Expectation {
+            prefix: MaskedBytes::parse_hex("AA55").expect("Failed to parse"),
+            exact: MaskedBytes::parse_hex("AA55 ?? ?? 01").expect("Failed to parse"),
+            regex: None,
+            json: Vec::new(),
+            capture: Vec::new(),
+            measure: Vec::new(),
+        };
+
+        assert!(exact.matches(b"Hello World!"));
+        assert!(exact.picks_out(b"Hello Moon!"));
+        assert!(!exact.matches(b"Hello Moon!"));
+        assert!(!exact.picks_out(b"Unrelated"));
+
+        assert!(pattern.matches(b"T=21"));
+        assert!(!pattern.picks_out(b"T=hot"));
+
+        assert!(binary.matches(&[0xaa, 0x55, 0x00, 0x2a, 0x01]));
+        assert!(binary.picks_out(&[0xaa, 0x55, 0x00, 0x2a, 0x02]));
+        assert!(!binary.matches(&[0xaa, 0x55, 0x00, 0x2a, 0x02]));
+    }
+
+    #[test]
+    fn pattern_eq_pass() {
+        assert_eq!(
+            Pattern::new("a+").expect("Failed to compile pattern"),
+            Pattern::new("a+").expect("Failed to compile pattern")
+        );
+    }
+}
@@ -0,0 +1,304 @@
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use std::fmt;
+
+use super::matcher::Pattern;
+use crate::connection::framing::Endian;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum MeasurementSource {
+    Group(String),
+    Bytes {
+        offset: usize,
+        width: usize,
+        endian: Endian,
+        signed: bool,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Bounds {
+    Range { min: Option<f64>, max: Option<f64> },
+    Tolerance { nominal: f64, tolerance: f64 },
+}
+
+impl Bounds {
+    pub fn contains(&self, value: f64) -> bool {
+        match *self {
+            Bounds::Range { min, max } => {
+                min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+            }
+            Bounds::Tolerance { nominal, tolerance } => (value - nominal).abs() <= tolerance,
+        }
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        match *self {
+            Bounds::Range { min, .. } => min,
+            Bounds::Tolerance { nominal, tolerance } => Some(nominal - tolerance),
+        }
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        match *self {
+            Bounds::Range { max, .. } => max,
+            Bounds::Tolerance { nominal, tolerance } => Some(nominal + tolerance),
+        }
+    }
+}
+
+impl fmt::Display for Bounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Bounds::Range {
+                min: Some(min),
+                max: Some(max),
+            } => write!(f, "between {min} and {max}"),
+            Bounds::Range {
+                min: Some(min),
+                max: None,
+            } => write!(f, "at least {min}"),
+            Bounds::Range {
+                min: None,
+                max: Some(max),
+            } => write!(f, "at most {max}"),
+            Bounds::Range {
+                min: None,
+                max: None,
+            } => write!(f, "any value"),
+            Bounds::Tolerance { nominal, tolerance } => {
+                write!(f, "within {tolerance} of {nominal}")
+            }
+        }
+    }
+}
+
+// A number pulled out of a matched response, scaled into real units before it's checked
+#[derive(Debug, PartialEq, Clone)]
+pub struct Measurement {
+    pub name: String,
+    pub source: MeasurementSource,
+    pub scale: f64,
+    pub bounds: Option<Bounds>,
+}
+
+impl Measurement {
+    pub fn extract(&self, data: &[u8], pattern: Option<&Pattern>) -> Option<f64> {
+        let raw = match &self.source {
+            MeasurementSource::Group(group) => {
+                let text = pattern?.capture(data, group)?;
+                std::str::from_utf8(&text)
+                    .ok()?
+                    .trim()
+                    .parse::<f64>()
+                    .ok()?
+            }
+            MeasurementSource::Bytes {
+                offset,
+                width,
+                endian,
+                signed,
+            } => {
+                let bytes = data.get(*offset..offset.checked_add(*width)?)?;
+                let value = endian.read(bytes);
+                match signed {
+                    // Shifting up then back down carries the sign bit of the field into the i64
+                    true => {
+                        let unused = 64 - 8 * *width as u32;
+                        ((value << unused) as i64 >> unused) as f64
+                    }
+                    false => value as f64,
+                }
+            }
+        };
+        Some(raw * self.scale)
+    }
+}
+
+// Kept flat rather than flattening in the source, serde can't deny unknown fields through a flatten
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RawMeasurement {
+    #[serde(default)]
+    group: Option<String>,
+    #[serde(default)]
+    offset: Option<usize>,
+    #[serde(default)]
+    width: Option<usize>,
+    #[serde(default)]
+    endian: Option<Endian>,
+    #[serde(default)]
+    signed: Option<bool>,
+    #[serde(default)]
+    scale: Option<f64>,
+    #[serde(default)]
+    min: Option<f64>,
+    #[serde(default)]
+    max: Option<f64>,
+    #[serde(default)]
+    nominal: Option<f64>,
+    #[serde(default)]
+    tolerance: Option<f64>,
+}
+
+impl RawMeasurement {
+    pub fn into_measurement(self, name: String, regex: Option<&Pattern>) -> Result<Measurement> {
+        let byte_field = self.offset.is_some()
+            || self.width.is_some()
+            || self.endian.is_some()
+            || self.signed.is_some();
+        let source = match (self.group, self.offset, self.width) {
+            (Some(group), None, None) if !byte_field => {
+                if !regex.is_some_and(|pattern| pattern.has_group(&group)) {
+                    bail!("measure {name} needs a regex with a group named {group}");
+                }
+                MeasurementSource::Group(group)
+            }
+            (None, Some(offset), Some(width)) => {
+                if !(1..=8).contains(&width) {
+                    bail!("measure {name} has a width of {width}, it must be 1 to 8 bytes");
+                }
+                MeasurementSource::Bytes {
+                    offset,
+                    width,
+                    endian: self.endian.unwrap_or_default(),
+                    signed: self.signed.unwrap_or_default(),
+                }
+            }
+            _ => bail!(
+                "measure {name} needs either a group, or an offset and width with an optional endian and signed"
+            ),
+        };
+        let bounds = match (self.min, self.max, self.nominal, self.tolerance) {
+            (None, None, None, None) => None,
+            (min, max, None, None) => {
+                if let (Some(min), Some(max)) = (min, max)
+                    && min > max
+                {
+                    bail!("measure {name} has a min of {min} which is more than its max of {max}");
+                }
+                Some(Bounds::Range { min, max })
+            }
+            (None, None, Some(nominal), Some(tolerance)) => {
+                if tolerance < 0.0 {
+                    bail!("measure {name} has a negative tolerance");
+                }
+                Some(Bounds::Tolerance { nominal, tolerance })
+            }
+            _ => bail!("measure {name} needs either min and/or max, or both nominal and tolerance"),
+        };
+        Ok(Measurement {
+            name,
+            source,
+            scale: self.scale.unwrap_or(1.0),
+            bounds,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn measurement(raw: &str, regex: Option<&Pattern>) -> Result<Measurement> {
+        serde_json::from_str::<RawMeasurement>(raw)
+            .expect("Failed to deserialize")
+            .into_measurement(String::from("value"), regex)
+    }
+
+    #[test]
+    fn measurement_extract_group_pass() {
+        let pattern = Pattern::new(r"^V=(?<volts>\S+)$").expect("Failed to compile pattern");
+        let volts = measurement(
+            r#"{ "group": "volts", "min": 3.2, "max": 3.4 }"#,
+            Some(&pattern),
+        )
+        .expect("Failed to make measurement");
+
+        assert_eq!(volts.extract(b"V=3.31", Some(&pattern)), Some(3.31));
+        assert_eq!(volts.extract(b"V=high", Some(&pattern)), None);
+        assert_eq!(
+            volts.bounds,
+            Some(Bounds::Range {
+                min: Some(3.2),
+                max: Some(3.4)
+            })
+        );
+    }
+
+    #[test]
+    fn measurement_extract_bytes_pass() {
+        let data = [0xaa, 0x01, 0xf4, 0xff, 0x38];
+        let big = measurement(r#"{ "offset": 1, "width": 2 }"#, None)
+            .expect("Failed to make measurement");
+        let little = measurement(
+            r#"{ "offset": 3, "width": 2, "endian": "little", "signed": true, "scale": 0.5 }"#,
+            None,
+        )
+        .expect("Failed to make measurement");
+        let negative = measurement(r#"{ "offset": 3, "width": 1, "signed": true }"#, None)
+            .expect("Failed to make measurement");
+
+        assert_eq!(big.extract(&data, None), Some(500.0));
+        assert_eq!(little.extract(&data, None), Some(7295.5));
+        assert_eq!(negative.extract(&data, None), Some(-1.0));
+        assert_eq!(big.extract(&data[..2], None), None);
+    }
+
+    #[test]
+    fn raw_measurement_fail_misspelled_field() {
+        for raw in [
+            r#"{ "group": "volts", "mn": 3.2 }"#,
+            r#"{ "group": "volts", "nominal": 5, "tolerence": 0.25 }"#,
+            r#"{ "offset": 0, "width": 2, "endain": "little" }"#,
+        ] {
+            assert!(
+                serde_json::from_str::<RawMeasurement>(raw).is_err(),
+                "Somehow deserialized {raw}"
+            );
+        }
+    }
+
+    #[test]
+    fn bounds_contains_pass() {
+        let range = Bounds::Range {
+            min: Some(-70.0),
+            max: None,
+        };
+        let tolerance = Bounds::Tolerance {
+            nominal: 5.0,
+            tolerance: 0.25,
+        };
+
+        assert!(range.contains(-70.0));
+        assert!(!range.contains(-70.5));
+        assert!(tolerance.contains(4.75));
+        assert!(!tolerance.contains(5.3));
+        assert_eq!((tolerance.min(), tolerance.max()), (Some(4.75), Some(5.25)));
+        assert_eq!(range.to_string(), "at least -70");
+        assert_eq!(tolerance.to_string(), "within 0.25 of 5");
+    }
+
+    #[test]
+    fn raw_measurement_fail() {
+        let pattern = Pattern::new(r"^V=(?<volts>\S+)$").expect("Failed to compile pattern");
+        for raw in [
+            r#"{ "group": "amps" }"#,
+            r#"{ "offset": 0, "width": 9 }"#,
+            r#"{ "offset": 0, "width": 0 }"#,
+            r#"{ "group": "volts", "min": 2, "max": 1 }"#,
+            r#"{ "group": "volts", "nominal": 2 }"#,
+            r#"{ "group": "volts", "min": 1, "nominal": 2, "tolerance": 1 }"#,
+            r#"{ "group": "volts", "nominal": 2, "tolerance": -1 }"#,
+            r#"{ "group": "volts", "offset": 0, "width": 1 }"#,
+            r#"{ "group": "volts", "signed": true }"#,
+            r#"{ "offset": 0 }"#,
+        ] {
+            assert!(
+                measurement(raw, Some(&pattern)).is_err(),
+                "Somehow made a measurement from {raw}"
+            );
+        }
+    }
+}
@@ -0,0 +1,433 @@
+use anyhow::{Result, bail};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+
+use super::matcher::Pattern;
+
+// Values captured from responses, kept as bytes so binary fields survive the round trip
+pub type Variables = HashMap<String, Vec<u8>>;
+
+// Values known before the run starts, filled into scenario files while they're parsed
+pub type Definitions = HashMap<String, String>;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum CaptureSource {
+    Group(String),
+    Bytes { offset: usize, length: usize },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Capture {
+    pub name: String,
+    pub source: CaptureSource,
+}
+
+impl Capture {
+    pub fn extract(&self, data: &[u8], pattern: Option<&Pattern>) -> Option<Vec<u8>> {
+        match &self.source {
+            CaptureSource::Group(group) => pattern?.capture(data, group),
+            CaptureSource::Bytes { offset, length } => data
+                .get(*offset..offset.checked_add(*length)?)
+                .map(Vec::from),
+        }
+    }
+}
+
+enum Token<'a> {
+    Literal(&'a [u8]),
+    Placeholder(&'a str),
+}
+
+// A send split up once at parse time, so bytes that only look like ${name} are never read as one
+#[derive(Debug, PartialEq, Clone)]
+pub enum Segment {
+    Literal(Vec<u8>),
+    Placeholder(String),
+}
+
+pub fn is_name(name: &[u8]) -> bool {
+    match name.split_first() {
+        Some((first, rest)) => {
+            (first.is_ascii_alphabetic() || *first == b'_')
+                && rest
+                    .iter()
+                    .all(|byte| byte.is_ascii_alphanumeric() || *byte == b'_')
+        }
+        None => false,
+    }
+}
+
+// Anything that doesn't look like ${name} is left alone as literal data
+fn tokens(template: &[u8]) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut literal_start = 0;
+    let mut index = 0;
+    while index + 1 < template.len() {
+        if &template[index..index + 2] == b"${"
+            && let Some(length) = template[index + 2..].iter().position(|byte| *byte == b'}')
+            && is_name(&template[index + 2..index + 2 + length])
+        {
+            if literal_start < index {
+                tokens.push(Token::Literal(&template[literal_start..index]));
+            }
+            let name = &template[index + 2..index + 2 + length];
+            // is_name only lets ASCII through
+            tokens.push(Token::Placeholder(
+                std::str::from_utf8(name).unwrap_or_default(),
+            ));
+            index += length + 3;
+            literal_start = index;
+        } else {
+            index += 1;
+        }
+    }
+    if literal_start < template.len() {
+        tokens.push(Token::Literal(&template[literal_start..]));
+    }
+    tokens
+}
+
+// Command line values win over the environment, which wins over the config file
+pub fn definitions(
+    config: Definitions,
+    environment: impl IntoIterator<Item = (String, String)>,
+    command_line: Vec<(String, String)>,
+) -> Definitions {
+    let mut definitions = config;
+    definitions.extend(environment);
+    definitions.extend(command_line);
+    definitions
+}
+
+// Entries that aren't UTF-8 or can't be a placeholder name are left out rather than failing the run
+pub fn environment(
+    variables: impl IntoIterator<Item = (OsString, OsString)>,
+) -> impl Iterator<Item = (String, String)> {
+    variables.into_iter().filter_map(|(name, value)| {
+        let name = name.into_string().ok()?;
+        let value = value.into_string().ok()?;
+        is_name(name.as_bytes()).then_some((name, value))
+    })
+}
+
+// Names in keep are left as written, anything else without a definition is handed back unresolved
+pub fn resolve(
+    template: &str,
+    definitions: &Definitions,
+    keep: &HashSet<String>,
+) -> (String, Vec<String>) {
+    let mut resolved = Vec::with_capacity(template.len());
+    let mut unresolved = Vec::new();
+    for token in tokens(template.as_bytes()) {
+        match token {
+            Token::Literal(literal) => resolved.extend_from_slice(literal),
+            Token::Placeholder(name) if keep.contains(name) => {
+                resolved.extend(format!("${{{name}}}").into_bytes());
+            }
+            Token::Placeholder(name) => match definitions.get(name) {
+                Some(value) => resolved.extend_from_slice(value.as_bytes()),
+                None => {
+                    unresolved.push(String::from(name));
+                    resolved.extend(format!("${{{name}}}").into_bytes());
+                }
+            },
+        }
+    }
+    // Only ever split on ASCII, so the pieces are still valid UTF-8
+    (String::from_utf8_lossy(&resolved).into_owned(), unresolved)
+}
+
+// Definitions are filled in as text before decoding, names in keep are left for substitute
+pub fn parse_template(
+    template: &str,
+    definitions: &Definitions,
+    keep: &HashSet<String>,
+    decode: fn(&str) -> Result<Vec<u8>>,
+) -> Result<(Vec<Segment>, Vec<String>)> {
+    let mut parsed = Vec::new();
+    let mut unresolved = Vec::new();
+    let mut text = String::new();
+    for token in tokens(template.as_bytes()) {
+        match token {
+            // Only ever split on ASCII, so the pieces are still valid UTF-8
+            Token::Literal(literal) => text.push_str(&String::from_utf8_lossy(literal)),
+            Token::Placeholder(name) if keep.contains(name) => {
+                if !text.is_empty() {
+                    parsed.push(Segment::Literal(decode(&text)?));
+                    text.clear();
+                }
+                parsed.push(Segment::Placeholder(String::from(name)));
+            }
+            Token::Placeholder(name) => match definitions.get(name) {
+                Some(value) => text.push_str(value),
+                None => unresolved.push(String::from(name)),
+            },
+        }
+    }
+    if !text.is_empty() {
+        parsed.push(Segment::Literal(decode(&text)?));
+    }
+    Ok((parsed, unresolved))
+}
+
+pub fn decode_text(text: &str) -> Result<Vec<u8>> {
+    Ok(Vec::from(text))
+}
+
+pub fn decode_hex(text: &str) -> Result<Vec<u8>> {
+    Ok(hex::decode(text)?)
+}
+
+pub fn substitute(template: &[Segment], variables: &Variables) -> Result<Vec<u8>> {
+    let mut substituted = Vec::new();
+    for segment in template {
+        match segment {
+            Segment::Literal(literal) => substituted.extend_from_slice(literal),
+            Segment::Placeholder(name) => match variables.get(name) {
+                Some(value) => substituted.extend_from_slice(value),
+                None => bail!("Variable {name} has not been captured"),
+            },
+        }
+    }
+    Ok(substituted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn text(template: &str) -> Vec<Segment> {
+        let (parsed, _) =
+            parse_template(template, &Definitions::new(), &HashSet::new(), decode_text)
+                .expect("Failed to parse template");
+        parsed
+    }
+
+    #[test]
+    fn substitute_pass() {
+        let variables = Variables::from([
+            (String::from("device_id"), Vec::from("00C0FFEE")),
+            (String::from("n"), vec![0x01]),
+        ]);
+        let template = vec![
+            Segment::Literal(Vec::from("SET ")),
+            Segment::Placeholder(String::from("device_id")),
+            Segment::Literal(Vec::from(" ")),
+            Segment::Placeholder(String::from("n")),
+        ];
+
+        assert_eq!(
+            substitute(&template, &variables).expect("Failed to substitute"),
+            b"SET 00C0FFEE \x01"
+        );
+    }
+
+    #[test]
+    fn substitute_pass_not_a_placeholder() {
+        let template = text("cost: $5 ${ } ${1abc} ${unclosed");
+
+        assert_eq!(
+            substitute(&template, &Variables::new()).expect("Failed to substitute"),
+            b"cost: $5 ${ } ${1abc} ${unclosed"
+        );
+    }
+
+    #[test]
+    fn substitute_pass_captured_value_not_expanded() {
+        let template = vec![Segment::Placeholder(String::from("reply"))];
+        let variables = Variables::from([
+            (String::from("reply"), Vec::from("${token}")),
+            (String::from("token"), Vec::from("secret")),
+        ]);
+
+        assert_eq!(
+            substitute(&template, &variables).expect("Failed to substitute"),
+            b"${token}"
+        );
+    }
+
+    #[test]
+    fn substitute_fail_missing_variable() {
+        let template = vec![
+            Segment::Literal(Vec::from("SET ")),
+            Segment::Placeholder(String::from("token")),
+        ];
+        let error = substitute(&template, &Variables::new())
+            .expect_err("Somehow substituted a variable that doesn't exist");
+
+        assert!(error.to_string().contains("token"));
+    }
+
+    #[test]
+    fn definitions_precedence_pass() {
+        let definitions = definitions(
+            Definitions::from([
+                (String::from("port"), String::from("1")),
+                (String::from("baud"), String::from("9600")),
+                (String::from("model"), String::from("A")),
+            ]),
+            vec![
+                (String::from("baud"), String::from("115200")),
+                (String::from("model"), String::from("B")),
+            ],
+            vec![(String::from("model"), String::from("C"))],
+        );
+
+        assert_eq!(definitions["port"], "1");
+        assert_eq!(definitions["baud"], "115200");
+        assert_eq!(definitions["model"], "C");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn environment_skips_unusable_pass() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let environment: Vec<(String, String)> = environment([
+            (OsString::from("port"), OsString::from("/dev/ttyUSB0")),
+            (
+                OsString::from("bad_value"),
+                OsString::from_vec(vec![0xff, 0xfe]),
+            ),
+            (OsString::from_vec(vec![b'x', 0xff]), OsString::from("1")),
+            (OsString::from("ProgramFiles(x86)"), OsString::from("C:\\")),
+        ])
+        .collect();
+
+        assert_eq!(
+            environment,
+            vec![(String::from("port"), String::from("/dev/ttyUSB0"))]
+        );
+    }
+
+    #[test]
+    fn resolve_pass() {
+        let definitions = Definitions::from([
+            (String::from("model"), String::from("X200")),
+            (String::from("token"), String::from("ignored")),
+        ]);
+        let keep = HashSet::from([String::from("token")]);
+
+        assert_eq!(
+            resolve("SET ${model} ${token} ${serial}", &definitions, &keep),
+            (
+                String::from("SET X200 ${token} ${serial}"),
+                vec![String::from("serial")]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_template_hex_pass() {
+        let definitions = Definitions::from([(String::from("kind"), String::from("02"))]);
+        let keep = HashSet::from([String::from("token")]);
+        let (parsed, unresolved) =
+            parse_template("aa55${kind}${token}01", &definitions, &keep, decode_hex)
+                .expect("Failed to parse template");
+
+        assert!(unresolved.is_empty());
+        assert_eq!(
+            parsed,
+            vec![
+                Segment::Literal(vec![0xaa, 0x55, 0x02]),
+                Segment::Placeholder(String::from("token")),
+                Segment::Literal(vec![0x01]),
+            ]
+        );
+        assert_eq!(
+            substitute(
+                &parsed,
+                &Variables::from([(String::from("token"), vec![0xde, 0xad])])
+            )
+            .expect("Failed to substitute"),
+            vec![0xaa, 0x55, 0x02, 0xde, 0xad, 0x01]
+        );
+    }
+
+    #[test]
+    fn parse_template_hex_pass_bytes_look_like_placeholder() {
+        // 24 7b 69 64 7d is ${id} once decoded, it's still just data
+        let (parsed, unresolved) = parse_template(
+            "aa247b69647d01",
+            &Definitions::new(),
+            &HashSet::from([String::from("id")]),
+            decode_hex,
+        )
+        .expect("Failed to parse template");
+
+        assert!(unresolved.is_empty());
+        assert_eq!(
+            substitute(&parsed, &Variables::new()).expect("Failed to substitute"),
+            b"\xaa${id}\x01"
+        );
+    }
+
+    #[test]
+    fn parse_template_definition_not_expanded_pass() {
+        let definitions = Definitions::from([
+            (String::from("greeting"), String::from("hi ${name}")),
+            (String::from("name"), String::from("there")),
+        ]);
+        let (parsed, unresolved) =
+            parse_template("${greeting}!", &definitions, &HashSet::new(), decode_text)
+                .expect("Failed to parse template");
+
+        assert!(unresolved.is_empty());
+        assert_eq!(parsed, vec![Segment::Literal(Vec::from("hi ${name}!"))]);
+    }
+
+    #[test]
+    fn parse_template_unresolved_pass() {
+        let (_, unresolved) = parse_template(
+            "SET ${port} ${baud}",
+            &Definitions::new(),
+            &HashSet::new(),
+            decode_text,
+        )
+        .expect("Failed to parse template");
+
+        assert_eq!(unresolved, vec![String::from("port"), String::from("baud")]);
+    }
+
+    #[test]
+    fn parse_template_hex_fail_odd_literal() {
+        assert!(
+            parse_template(
+                "aa5${token}",
+                &Definitions::new(),
+                &HashSet::from([String::from("token")]),
+                decode_hex
+            )
+            .is_err(),
+            "Somehow decoded half a byte"
+        );
+    }
+
+    #[test]
+    fn capture_extract_pass() {
+        let pattern = Pattern::new(r"^ID:(?<id>[0-9A-F]+)$").expect("Failed to compile pattern");
+        let group = Capture {
+            name: String::from("device_id"),
+            source: CaptureSource::Group(String::from("id")),
+        };
+        let bytes = Capture {
+            name: String::from("kind"),
+            source: CaptureSource::Bytes {
+                offset: 1,
+                length: 2,
+            },
+        };
+
+        assert_eq!(
+            group.extract(b"ID:00C0FFEE", Some(&pattern)),
+            Some(Vec::from("00C0FFEE"))
+        );
+        assert_eq!(group.extract(b"ID:00C0FFEE", None), None);
+        assert_eq!(
+            bytes.extract(&[0xaa, 0x01, 0x02, 0x03], None),
+            Some(vec![0x01, 0x02])
+        );
+        assert_eq!(bytes.extract(&[0xaa, 0x01], None), None);
+    }
+}
@@ -1,11 +1,15 @@
-use anyhow::Result;
 use clap::Parser;
 use env_logger::{self, TimestampPrecision};
+use interaction::command::Repeat;
+use interaction::{duration, variables};
 use log::info;
+use std::process::ExitCode;
+use std::time::Duration;
 use threads::controller;
 
 mod connection;
 mod interaction;
+mod results;
 mod threads;
 
 #[derive(Parser, Debug)]
@@ -13,9 +17,47 @@ mod threads;
 pub struct Args {
     #[arg(short, long, default_value = "./config.json")]
     config_file: String,
+    #[arg(
+        long = "var",
+        value_name = "NAME=VALUE",
+        value_parser = parse_variable,
+        help = "Fills in ${NAME} in scenario files, can be given more than once"
+    )]
+    variables: Vec<(String, String)>,
+    #[arg(
+        long,
+        value_name = "COUNT",
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with = "duration",
+        help = "Runs all the scenarios in the config this many times"
+    )]
+    repeat: Option<u32>,
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        help = "Keeps running all the scenarios in the config until this much time has passed, e.g. 8h"
+    )]
+    duration: Option<Duration>,
 }
 
-fn main() -> Result<()> {
+fn parse_variable(argument: &str) -> Result<(String, String), String> {
+    let Some((name, value)) = argument.split_once('=') else {
+        return Err(String::from("expected NAME=VALUE"));
+    };
+    if !variables::is_name(name.as_bytes()) {
+        return Err(format!(
+            "{name:?} can only use letters, digits and underscores"
+        ));
+    }
+    Ok((String::from(name), String::from(value)))
+}
+
+fn parse_duration(argument: &str) -> Result<Duration, String> {
+    duration::parse(argument).map_err(|msg| msg.to_string())
+}
+
+fn main() -> ExitCode {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
         .format_timestamp(Some(TimestampPrecision::Millis))
         .write_style(env_logger::WriteStyle::Always)
@@ -24,9 +66,14 @@ fn main() -> Result<()> {
     info!("Parsing config file");
     let args = Args::parse();
 
+    let repeat = match (args.repeat, args.duration) {
+        (_, Some(duration)) => Repeat::For(duration),
+        (Some(times), None) => Repeat::Times(times),
+        (None, None) => Repeat::Times(1),
+    };
     // Don't actually spawn a thread but can be spawned as a separate thread from main if needed
-    controller::thread(args.config_file)?;
+    let outcome = controller::thread(args.config_file, args.variables, repeat);
 
     info!("Scenario Runner has finished running");
-    Ok(())
+    ExitCode::from(outcome)
 }
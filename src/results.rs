@@ -0,0 +1,577 @@
+use chrono::{DateTime, Local};
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use crate::interaction::matcher::MaskedBytes;
+
+pub mod json;
+pub mod junit;
+pub mod transcript;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Pass,
+    Mismatch,
+    Timeout,
+    Skipped,
+    ParseError,
+}
+
+impl Verdict {
+    pub fn is_pass(&self) -> bool {
+        matches!(self, Verdict::Pass)
+    }
+}
+
+// Ordered by severity, a run's outcome only ever escalates
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Passed,
+    CommandsFailed,
+    ConfigurationError,
+    ConnectionError,
+}
+
+impl From<Outcome> for ExitCode {
+    fn from(value: Outcome) -> Self {
+        ExitCode::from(match value {
+            Outcome::Passed => 0,
+            Outcome::CommandsFailed => 1,
+            Outcome::ConfigurationError => 2,
+            Outcome::ConnectionError => 3,
+        })
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Payload {
+    pub hex: String,
+    pub text: String,
+}
+
+impl From<&[u8]> for Payload {
+    fn from(value: &[u8]) -> Self {
+        Payload {
+            hex: hex::encode(value),
+            text: String::from_utf8_lossy(value).into_owned(),
+        }
+    }
+}
+
+// Ignored bytes show up as ?? in the hex, there's nothing better to put in the text
+impl From<&MaskedBytes> for Payload {
+    fn from(value: &MaskedBytes) -> Self {
+        Payload {
+            hex: value.to_hex(),
+            text: String::from_utf8_lossy(value.value()).into_owned(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CommandResult {
+    pub index: usize,
+    pub description: Option<String>,
+    pub verdict: Verdict,
+    pub expected: Option<Payload>,
+    pub received: Option<Payload>,
+    #[serde(rename = "elapsed_ms", serialize_with = "serialize_millis")]
+    pub elapsed: Duration,
+    // From the send until the last expected response, compared against the timeout
+    #[serde(rename = "latency_ms", serialize_with = "serialize_optional_millis")]
+    pub latency: Option<Duration>,
+    #[serde(rename = "timeout_ms", serialize_with = "serialize_optional_millis")]
+    pub timeout: Option<Duration>,
+    pub measurements: Vec<MeasurementResult>,
+    // Every try of a command that retries or polls, the fields above are from the last one
+    pub attempts: Vec<AttemptResult>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AttemptResult {
+    pub verdict: Verdict,
+    pub received: Option<Payload>,
+    #[serde(rename = "elapsed_ms", serialize_with = "serialize_millis")]
+    pub elapsed: Duration,
+    pub error: Option<String>,
+}
+
+// Kept whether or not it was in bounds, so values can be tracked from one run to the next
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct MeasurementResult {
+    pub name: String,
+    pub value: f64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub verdict: Verdict,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ScenarioResult {
+    pub scenario: PathBuf,
+    // Counts from 1, runs with --repeat or --duration go through the scenarios more than once
+    pub iteration: u32,
+    pub verdict: Verdict,
+    pub error: Option<String>,
+    pub commands: Vec<CommandResult>,
+}
+
+impl ScenarioResult {
+    pub fn new(scenario: PathBuf, iteration: u32) -> Self {
+        ScenarioResult {
+            scenario,
+            iteration,
+            verdict: Verdict::Pass,
+            error: None,
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn with_error(scenario: PathBuf, iteration: u32, verdict: Verdict, error: String) -> Self {
+        ScenarioResult {
+            scenario,
+            iteration,
+            verdict,
+            error: Some(error),
+            commands: Vec::new(),
+        }
+    }
+
+    // The first command that doesn't pass decides the verdict of the whole scenario
+    pub fn push(&mut self, command: CommandResult) {
+        if self.verdict.is_pass() && !command.verdict.is_pass() {
+            self.verdict = command.verdict;
+        }
+        self.commands.push(command);
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Summary {
+    pub scenarios: usize,
+    pub commands: usize,
+    pub passed: usize,
+    pub mismatched: usize,
+    pub timed_out: usize,
+    pub skipped: usize,
+    pub parse_errors: usize,
+    pub iterations: u32,
+    pub iterations_passed: u32,
+    // Share of commands that passed, from 0 to 1
+    pub pass_rate: f64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct IterationSummary {
+    pub iteration: u32,
+    pub verdict: Verdict,
+    pub scenarios: usize,
+    pub scenarios_passed: usize,
+    pub commands: usize,
+    pub commands_passed: usize,
+    pub pass_rate: f64,
+}
+
+fn pass_rate(passed: usize, total: usize) -> f64 {
+    match total {
+        0 => 0.0,
+        _ => passed as f64 / total as f64,
+    }
+}
+
+// Latencies of the same command gathered over every time its scenario ran
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LatencyStatistics {
+    pub scenario: PathBuf,
+    pub index: usize,
+    pub samples: usize,
+    #[serde(rename = "min_ms", serialize_with = "serialize_millis")]
+    pub min: Duration,
+    #[serde(rename = "mean_ms", serialize_with = "serialize_millis")]
+    pub mean: Duration,
+    #[serde(rename = "p95_ms", serialize_with = "serialize_millis")]
+    pub p95: Duration,
+    #[serde(rename = "max_ms", serialize_with = "serialize_millis")]
+    pub max: Duration,
+}
+
+impl LatencyStatistics {
+    pub fn new(scenario: PathBuf, index: usize, mut latencies: Vec<Duration>) -> Option<Self> {
+        latencies.sort();
+        let samples = latencies.len();
+        // Nearest rank, so the p95 is always a latency that was actually measured
+        let p95 = *latencies.get((samples * 95).div_ceil(100).checked_sub(1)?)?;
+        Some(LatencyStatistics {
+            scenario,
+            index,
+            samples,
+            min: *latencies.first()?,
+            mean: latencies.iter().sum::<Duration>() / u32::try_from(samples).ok()?,
+            p95,
+            max: *latencies.last()?,
+        })
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RunResults {
+    pub started: DateTime<Local>,
+    pub finished: Option<DateTime<Local>>,
+    pub outcome: Outcome,
+    // Why the run stopped before its scenarios could run, if it did
+    pub error: Option<String>,
+    pub summary: Summary,
+    pub iterations: Vec<IterationSummary>,
+    pub latency_statistics: Vec<LatencyStatistics>,
+    pub scenarios: Vec<ScenarioResult>,
+}
+
+impl RunResults {
+    pub fn new() -> Self {
+        RunResults {
+            started: Local::now(),
+            finished: None,
+            outcome: Outcome::Passed,
+            error: None,
+            summary: Summary::default(),
+            iterations: Vec::new(),
+            latency_statistics: Vec::new(),
+            scenarios: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, scenario: ScenarioResult) {
+        self.summary.scenarios += 1;
+        // Scenarios that never got to run their commands still count once towards the summary
+        if scenario.commands.is_empty() && scenario.error.is_some() {
+            match scenario.verdict {
+                Verdict::ParseError => self.summary.parse_errors += 1,
+                Verdict::Skipped => self.summary.skipped += 1,
+                _ => {}
+            }
+        }
+        self.escalate(match scenario.verdict {
+            Verdict::Pass => Outcome::Passed,
+            Verdict::ParseError => Outcome::ConfigurationError,
+            Verdict::Skipped if scenario.commands.is_empty() => Outcome::ConfigurationError,
+            _ => Outcome::CommandsFailed,
+        });
+        for command in scenario.commands.iter() {
+            self.summary.commands += 1;
+            match command.verdict {
+                Verdict::Pass => self.summary.passed += 1,
+                Verdict::Mismatch => self.summary.mismatched += 1,
+                Verdict::Timeout => self.summary.timed_out += 1,
+                Verdict::Skipped => self.summary.skipped += 1,
+                Verdict::ParseError => self.summary.parse_errors += 1,
+            }
+        }
+        self.scenarios.push(scenario);
+    }
+
+    pub fn escalate(&mut self, outcome: Outcome) {
+        self.outcome = self.outcome.max(outcome);
+    }
+
+    pub fn abort(&mut self, outcome: Outcome, error: String) {
+        self.escalate(outcome);
+        self.error = Some(error);
+    }
+
+    pub fn finish(&mut self) {
+        self.finished = Some(Local::now());
+        let mut iterations: BTreeMap<u32, IterationSummary> = BTreeMap::new();
+        for scenario in self.scenarios.iter() {
+            let summary =
+                iterations
+                    .entry(scenario.iteration)
+                    .or_insert_with(|| IterationSummary {
+                        iteration: scenario.iteration,
+                        verdict: Verdict::Pass,
+                        scenarios: 0,
+                        scenarios_passed: 0,
+                        commands: 0,
+                        commands_passed: 0,
+                        pass_rate: 0.0,
+                    });
+            // Same rule as within a scenario, the first thing that fails sets the verdict
+            if summary.verdict.is_pass() && !scenario.verdict.is_pass() {
+                summary.verdict = scenario.verdict;
+            }
+            summary.scenarios += 1;
+            summary.scenarios_passed += usize::from(scenario.verdict.is_pass());
+            summary.commands += scenario.commands.len();
+            summary.commands_passed += scenario
+                .commands
+                .iter()
+                .filter(|command| command.verdict.is_pass())
+                .count();
+        }
+        self.iterations = iterations
+            .into_values()
+            .map(|mut summary| {
+                summary.pass_rate = pass_rate(summary.commands_passed, summary.commands);
+                summary
+            })
+            .collect();
+        self.summary.iterations = self.iterations.len() as u32;
+        self.summary.iterations_passed = self
+            .iterations
+            .iter()
+            .filter(|summary| summary.verdict.is_pass())
+            .count() as u32;
+        self.summary.pass_rate = pass_rate(self.summary.passed, self.summary.commands);
+        let mut latencies: BTreeMap<(PathBuf, usize), Vec<Duration>> = BTreeMap::new();
+        for scenario in self.scenarios.iter() {
+            for command in scenario.commands.iter() {
+                if let Some(latency) = command.latency {
+                    latencies
+                        .entry((scenario.scenario.clone(), command.index))
+                        .or_default()
+                        .push(latency);
+                }
+            }
+        }
+        self.latency_statistics = latencies
+            .into_iter()
+            .filter_map(|((scenario, index), latencies)| {
+                LatencyStatistics::new(scenario, index, latencies)
+            })
+            .collect();
+    }
+}
+
+fn serialize_millis<S: Serializer>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(value.as_secs_f64() * 1000.0)
+}
+
+fn serialize_optional_millis<S: Serializer>(
+    value: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => serialize_millis(value, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn command_result(verdict: Verdict) -> CommandResult {
+        CommandResult {
+            index: 0,
+            description: None,
+            verdict,
+            expected: None,
+            received: None,
+            elapsed: Duration::from_millis(5),
+            latency: None,
+            timeout: None,
+            measurements: Vec::new(),
+            attempts: Vec::new(),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn payload_from_bytes_pass() {
+        let payload = Payload::from(b"OK\xff".as_slice());
+
+        assert_eq!(payload.hex, "4f4bff");
+        assert_eq!(payload.text, "OK\u{fffd}");
+    }
+
+    #[test]
+    fn payload_from_masked_bytes_pass() {
+        let masked = MaskedBytes::parse_hex("4f4b??").expect("Failed to parse");
+
+        let payload = Payload::from(&masked);
+
+        assert_eq!(payload.hex, "4f4b??");
+        assert_eq!(payload.text, "OK\0");
+    }
+
+    #[test]
+    fn scenario_result_push_first_failure_wins() {
+        let mut scenario = ScenarioResult::new(PathBuf::from("scenario"), 1);
+        scenario.push(command_result(Verdict::Pass));
+        assert_eq!(scenario.verdict, Verdict::Pass);
+
+        scenario.push(command_result(Verdict::Timeout));
+        scenario.push(command_result(Verdict::Mismatch));
+        assert_eq!(scenario.verdict, Verdict::Timeout);
+        assert_eq!(scenario.commands.len(), 3);
+    }
+
+    #[test]
+    fn run_results_push_summary_pass() {
+        let mut run = RunResults::new();
+        let mut scenario = ScenarioResult::new(PathBuf::from("scenario"), 1);
+        scenario.push(command_result(Verdict::Pass));
+        scenario.push(command_result(Verdict::Mismatch));
+        scenario.push(command_result(Verdict::Skipped));
+        run.push(scenario);
+        run.push(ScenarioResult::with_error(
+            PathBuf::from("broken"),
+            1,
+            Verdict::ParseError,
+            String::from("bad json"),
+        ));
+
+        let assert_summary = Summary {
+            scenarios: 2,
+            commands: 3,
+            passed: 1,
+            mismatched: 1,
+            timed_out: 0,
+            skipped: 1,
+            parse_errors: 1,
+            ..Summary::default()
+        };
+        assert_eq!(run.summary, assert_summary);
+    }
+
+    #[test]
+    fn run_results_finish_iterations_pass() {
+        let mut run = RunResults::new();
+        for (iteration, verdicts) in [
+            (1, [Verdict::Pass, Verdict::Pass]),
+            (2, [Verdict::Pass, Verdict::Timeout]),
+        ] {
+            for verdict in verdicts {
+                let mut scenario = ScenarioResult::new(PathBuf::from("scenario"), iteration);
+                scenario.push(command_result(Verdict::Pass));
+                scenario.push(command_result(verdict));
+                run.push(scenario);
+            }
+        }
+
+        run.finish();
+
+        assert_eq!(
+            run.iterations,
+            vec![
+                IterationSummary {
+                    iteration: 1,
+                    verdict: Verdict::Pass,
+                    scenarios: 2,
+                    scenarios_passed: 2,
+                    commands: 4,
+                    commands_passed: 4,
+                    pass_rate: 1.0,
+                },
+                IterationSummary {
+                    iteration: 2,
+                    verdict: Verdict::Timeout,
+                    scenarios: 2,
+                    scenarios_passed: 1,
+                    commands: 4,
+                    commands_passed: 3,
+                    pass_rate: 0.75,
+                },
+            ]
+        );
+        assert_eq!(
+            (
+                run.summary.iterations,
+                run.summary.iterations_passed,
+                run.summary.pass_rate
+            ),
+            (2, 1, 0.875)
+        );
+    }
+
+    #[test]
+    fn run_results_outcome_escalates_pass() {
+        let mut run = RunResults::new();
+        let mut scenario = ScenarioResult::new(PathBuf::from("scenario"), 1);
+        scenario.push(command_result(Verdict::Pass));
+        run.push(scenario.clone());
+        assert_eq!(run.outcome, Outcome::Passed);
+
+        scenario.push(command_result(Verdict::Timeout));
+        run.push(scenario);
+        assert_eq!(run.outcome, Outcome::CommandsFailed);
+
+        run.push(ScenarioResult::with_error(
+            PathBuf::from("missing"),
+            1,
+            Verdict::Skipped,
+            String::from("Scenario file does not exist"),
+        ));
+        assert_eq!(run.outcome, Outcome::ConfigurationError);
+
+        run.escalate(Outcome::ConnectionError);
+        run.escalate(Outcome::CommandsFailed);
+        assert_eq!(run.outcome, Outcome::ConnectionError);
+    }
+
+    #[test]
+    fn latency_statistics_pass() {
+        let latencies = (1..=20).map(Duration::from_millis).collect();
+
+        let statistics = LatencyStatistics::new(PathBuf::from("scenario"), 3, latencies)
+            .expect("Failed to work out statistics");
+
+        assert_eq!(statistics.samples, 20);
+        assert_eq!(statistics.min, Duration::from_millis(1));
+        assert_eq!(statistics.mean, Duration::from_micros(10_500));
+        assert_eq!(statistics.p95, Duration::from_millis(19));
+        assert_eq!(statistics.max, Duration::from_millis(20));
+        assert_eq!(
+            LatencyStatistics::new(PathBuf::from("scenario"), 3, Vec::new()),
+            None
+        );
+    }
+
+    #[test]
+    fn run_results_finish_latency_statistics_pass() {
+        let mut run = RunResults::new();
+        for latency in [30, 10, 20] {
+            let mut scenario = ScenarioResult::new(PathBuf::from("scenario"), 1);
+            let mut measured = command_result(Verdict::Pass);
+            measured.latency = Some(Duration::from_millis(latency));
+            scenario.push(measured);
+            // Commands that never got a response don't count
+            let mut unmeasured = command_result(Verdict::Timeout);
+            unmeasured.index = 1;
+            scenario.push(unmeasured);
+            run.push(scenario);
+        }
+
+        run.finish();
+
+        assert_eq!(
+            run.latency_statistics,
+            vec![LatencyStatistics {
+                scenario: PathBuf::from("scenario"),
+                index: 0,
+                samples: 3,
+                min: Duration::from_millis(10),
+                mean: Duration::from_millis(20),
+                p95: Duration::from_millis(30),
+                max: Duration::from_millis(30),
+            }]
+        );
+    }
+
+    #[test]
+    fn outcome_exit_code_pass() {
+        assert_eq!(ExitCode::from(Outcome::Passed), ExitCode::SUCCESS);
+        assert_eq!(ExitCode::from(Outcome::CommandsFailed), ExitCode::from(1));
+        assert_eq!(
+            ExitCode::from(Outcome::ConfigurationError),
+            ExitCode::from(2)
+        );
+        assert_eq!(ExitCode::from(Outcome::ConnectionError), ExitCode::from(3));
+    }
+}
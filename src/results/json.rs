@@ -0,0 +1,86 @@
+use anyhow::Result;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use super::RunResults;
+
+pub fn write_report(results: &RunResults, results_location: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(results_location)?;
+    let report_path = results_location.join(format!(
+        "results_{}.json",
+        results.started.format("%Y%m%d_%H%M%S")
+    ));
+
+    let writer = BufWriter::new(File::create(&report_path)?);
+    serde_json::to_writer_pretty(writer, results)?;
+    Ok(report_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::results::{CommandResult, MeasurementResult, Payload, ScenarioResult, Verdict};
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn write_report_pass() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let mut results = RunResults::new();
+        let mut scenario = ScenarioResult::new(PathBuf::from("scenario1"), 1);
+        scenario.push(CommandResult {
+            index: 0,
+            description: Some(String::from("Say hello")),
+            verdict: Verdict::Mismatch,
+            expected: Some(Payload::from(b"Hello".as_slice())),
+            received: Some(Payload::from(b"Help".as_slice())),
+            elapsed: Duration::from_millis(250),
+            latency: Some(Duration::from_millis(120)),
+            timeout: Some(Duration::from_millis(500)),
+            measurements: vec![MeasurementResult {
+                name: String::from("battery"),
+                value: 3.1,
+                min: Some(3.2),
+                max: None,
+                verdict: Verdict::Mismatch,
+            }],
+            attempts: Vec::new(),
+            error: None,
+        });
+        results.push(scenario);
+        results.finish();
+
+        let report_path = write_report(&results, &temp_dir.path().join("nested"))
+            .expect("Failed to write report");
+        let report: serde_json::Value = serde_json::from_reader(
+            File::open(&report_path).expect("Failed to open written report"),
+        )
+        .expect("Report was not valid JSON");
+
+        assert_eq!(report["summary"]["mismatched"], 1);
+        let command = &report["scenarios"][0]["commands"][0];
+        assert_eq!(command["verdict"], "mismatch");
+        assert_eq!(command["expected"]["text"], "Hello");
+        assert_eq!(command["received"]["hex"], "48656c70");
+        assert_eq!(command["elapsed_ms"], 250.0);
+        assert_eq!(command["latency_ms"], 120.0);
+        assert_eq!(command["measurements"][0]["name"], "battery");
+        assert_eq!(command["measurements"][0]["value"], 3.1);
+        assert_eq!(command["measurements"][0]["max"], serde_json::Value::Null);
+        assert_eq!(command["measurements"][0]["verdict"], "mismatch");
+        assert_eq!(command["timeout_ms"], 500.0);
+    }
+
+    #[test]
+    fn write_report_fail_location_is_file() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let blocking_file = temp_dir.path().join("results");
+        File::create(&blocking_file).expect("Failed to create blocking file");
+
+        let result = write_report(&RunResults::new(), &blocking_file);
+
+        assert!(result.is_err(), "Somehow wrote a report inside a file");
+    }
+}
@@ -0,0 +1,391 @@
+use anyhow::Result;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{CommandResult, RunResults, ScenarioResult, Verdict};
+
+pub fn write_report(results: &RunResults, results_location: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(results_location)?;
+    let report_path = results_location.join(format!(
+        "junit_{}.xml",
+        results.started.format("%Y%m%d_%H%M%S")
+    ));
+
+    fs::write(&report_path, render(results)?)?;
+    Ok(report_path)
+}
+
+fn render(results: &RunResults) -> Result<String> {
+    let mut suites = String::new();
+    let (mut tests, mut failures, mut errors, mut skipped) = (0, 0, 0, 0);
+    for scenario in results.scenarios.iter() {
+        let cases = scenario_cases(scenario);
+        tests += cases.len();
+        failures += count(&cases, Verdict::Mismatch) + count(&cases, Verdict::Timeout);
+        errors += count(&cases, Verdict::ParseError);
+        skipped += count(&cases, Verdict::Skipped);
+        render_suite(&mut suites, results, scenario, &cases)?;
+    }
+    // A run that never got to its scenarios still needs a failing case, or CI would see nothing wrong
+    if let Some(error) = &results.error {
+        tests += 1;
+        errors += 1;
+        writeln!(
+            suites,
+            "  <testsuite name=\"scenario-runner\" tests=\"1\" failures=\"0\" errors=\"1\" skipped=\"0\" time=\"0.000\" timestamp=\"{}\">",
+            results.started.format("%Y-%m-%dT%H:%M:%S"),
+        )?;
+        suites.push_str(
+            "    <testcase name=\"start run\" classname=\"scenario-runner\" time=\"0.000\">\n",
+        );
+        writeln!(
+            suites,
+            "      <error type=\"run_error\" message=\"{}\"/>",
+            escape(error)
+        )?;
+        suites.push_str("    </testcase>\n  </testsuite>\n");
+    }
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        xml,
+        "<testsuites name=\"scenario-runner\" tests=\"{tests}\" failures=\"{failures}\" errors=\"{errors}\" skipped=\"{skipped}\">"
+    )?;
+    xml.push_str(&suites);
+    xml.push_str("</testsuites>\n");
+    Ok(xml)
+}
+
+// Scenarios that never ran still get a single test case so CI dashboards show them
+fn scenario_cases(scenario: &ScenarioResult) -> Vec<CommandResult> {
+    let case = |description: &str, verdict: Verdict, error: Option<String>| CommandResult {
+        index: scenario.commands.len(),
+        description: Some(String::from(description)),
+        verdict,
+        expected: None,
+        received: None,
+        elapsed: Default::default(),
+        latency: None,
+        timeout: None,
+        measurements: Vec::new(),
+        attempts: Vec::new(),
+        error,
+    };
+    match &scenario.error {
+        Some(_) if scenario.commands.is_empty() => {
+            vec![case("load scenario", scenario.verdict, None)]
+        }
+        // A forbidden frame outside of any command would otherwise leave every case passing
+        Some(error) => {
+            let mut cases = scenario.commands.clone();
+            cases.push(case(
+                "outside of commands",
+                Verdict::Mismatch,
+                Some(error.clone()),
+            ));
+            cases
+        }
+        None => scenario.commands.clone(),
+    }
+}
+
+fn count(cases: &[CommandResult], verdict: Verdict) -> usize {
+    cases.iter().filter(|case| case.verdict == verdict).count()
+}
+
+fn render_suite(
+    xml: &mut String,
+    results: &RunResults,
+    scenario: &ScenarioResult,
+    cases: &[CommandResult],
+) -> Result<()> {
+    // Each pass over the scenarios gets its own suites, otherwise their names would clash
+    let suite_name = match results.iterations.len() {
+        0 | 1 => scenario.scenario.display().to_string(),
+        _ => format!(
+            "{} (iteration {})",
+            scenario.scenario.display(),
+            scenario.iteration
+        ),
+    };
+    let class_name = scenario
+        .scenario
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| suite_name.clone());
+    let suite_time: f64 = cases.iter().map(|case| case.elapsed.as_secs_f64()).sum();
+
+    writeln!(
+        xml,
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\" timestamp=\"{}\">",
+        escape(&suite_name),
+        cases.len(),
+        count(cases, Verdict::Mismatch) + count(cases, Verdict::Timeout),
+        count(cases, Verdict::ParseError),
+        count(cases, Verdict::Skipped),
+        suite_time,
+        results.started.format("%Y-%m-%dT%H:%M:%S"),
+    )?;
+
+    for case in cases {
+        let case_name = match &case.description {
+            Some(description) => description.clone(),
+            None => format!("command {}", case.index),
+        };
+        write!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+            escape(&case_name),
+            escape(&class_name),
+            case.elapsed.as_secs_f64()
+        )?;
+
+        let message = case
+            .error
+            .as_deref()
+            .or(scenario.error.as_deref())
+            .unwrap_or("Command was not run");
+        match case.verdict {
+            Verdict::Pass => xml.push_str("/>\n"),
+            // The error says why, e.g. which JSON fields or measurements were off
+            Verdict::Mismatch => {
+                xml.push_str(">\n");
+                writeln!(
+                    xml,
+                    "      <failure type=\"mismatch\" message=\"{}\">{}</failure>",
+                    escape(case.error.as_deref().unwrap_or("Response did not match")),
+                    escape(&describe_payloads(case))
+                )?;
+                xml.push_str("    </testcase>\n");
+            }
+            Verdict::Timeout => {
+                xml.push_str(">\n");
+                writeln!(
+                    xml,
+                    "      <failure type=\"timeout\" message=\"{}\">{}</failure>",
+                    escape(
+                        case.error
+                            .as_deref()
+                            .unwrap_or("Expected response was not received")
+                    ),
+                    escape(&describe_payloads(case))
+                )?;
+                xml.push_str("    </testcase>\n");
+            }
+            Verdict::Skipped => {
+                xml.push_str(">\n");
+                writeln!(xml, "      <skipped message=\"{}\"/>", escape(message))?;
+                xml.push_str("    </testcase>\n");
+            }
+            Verdict::ParseError => {
+                xml.push_str(">\n");
+                writeln!(
+                    xml,
+                    "      <error type=\"parse_error\" message=\"{}\"/>",
+                    escape(message)
+                )?;
+                xml.push_str("    </testcase>\n");
+            }
+        }
+    }
+    xml.push_str("  </testsuite>\n");
+    Ok(())
+}
+
+fn describe_payloads(case: &CommandResult) -> String {
+    let mut description = String::new();
+    if let Some(expected) = &case.expected {
+        description.push_str(&format!("Expected: {} ({})\n", expected.text, expected.hex));
+    }
+    match &case.received {
+        Some(received) => {
+            description.push_str(&format!("Received: {} ({})", received.text, received.hex))
+        }
+        None => description.push_str("Received: nothing"),
+    }
+    description
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // XML 1.0 can't carry most control characters, even escaped
+            '\t' | '\n' | '\r' => escaped.push(character),
+            c if (c as u32) < 0x20 => escaped.push('\u{fffd}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::results::{Outcome, Payload};
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    fn sample_results() -> RunResults {
+        let mut results = RunResults::new();
+        let mut scenario = ScenarioResult::new(PathBuf::from("./scenarios/boot.json"), 1);
+        scenario.push(CommandResult {
+            index: 0,
+            description: Some(String::from("Check <version>")),
+            verdict: Verdict::Pass,
+            expected: Some(Payload::from(b"v1".as_slice())),
+            received: Some(Payload::from(b"v1".as_slice())),
+            elapsed: Duration::from_millis(20),
+            latency: None,
+            timeout: None,
+            measurements: Vec::new(),
+            attempts: Vec::new(),
+            error: None,
+        });
+        scenario.push(CommandResult {
+            index: 1,
+            description: None,
+            verdict: Verdict::Timeout,
+            expected: Some(Payload::from(b"OK".as_slice())),
+            received: None,
+            elapsed: Duration::from_secs(1),
+            latency: None,
+            timeout: None,
+            measurements: Vec::new(),
+            attempts: Vec::new(),
+            error: None,
+        });
+        scenario.push(CommandResult {
+            index: 2,
+            description: Some(String::from("Read status")),
+            verdict: Verdict::Mismatch,
+            expected: Some(Payload::from(b"temp: less than 40".as_slice())),
+            received: Some(Payload::from(br#"{"temp":55}"#.as_slice())),
+            elapsed: Duration::from_millis(30),
+            latency: None,
+            timeout: None,
+            measurements: Vec::new(),
+            attempts: Vec::new(),
+            error: Some(String::from("temp: expected less than 40, got 55")),
+        });
+        results.push(scenario);
+        results.push(ScenarioResult::with_error(
+            PathBuf::from("./scenarios/broken.json"),
+            1,
+            Verdict::ParseError,
+            String::from("expected `,` or `]`"),
+        ));
+        results
+    }
+
+    #[test]
+    fn escape_pass() {
+        assert_eq!(
+            escape("<a href=\"x\">&'\u{1}"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&apos;\u{fffd}"
+        );
+    }
+
+    #[test]
+    fn render_pass() {
+        let xml = render(&sample_results()).expect("Failed to render report");
+
+        assert!(xml.contains(
+            "<testsuites name=\"scenario-runner\" tests=\"4\" failures=\"2\" errors=\"1\" skipped=\"0\">"
+        ));
+        assert!(
+            xml.contains("<testsuite name=\"./scenarios/boot.json\" tests=\"3\" failures=\"2\"")
+        );
+        assert!(xml.contains(
+            "<testcase name=\"Check &lt;version&gt;\" classname=\"boot\" time=\"0.020\"/>"
+        ));
+        assert!(xml.contains("<testcase name=\"command 1\" classname=\"boot\" time=\"1.000\">"));
+        assert!(
+            xml.contains(
+                "<failure type=\"timeout\" message=\"Expected response was not received\">"
+            )
+        );
+        assert!(xml.contains(
+            "<failure type=\"mismatch\" message=\"temp: expected less than 40, got 55\">"
+        ));
+        assert!(xml.contains("Received: nothing"));
+        assert!(xml.contains("<error type=\"parse_error\" message=\"expected `,` or `]`\"/>"));
+    }
+
+    #[test]
+    fn render_scenario_error_after_commands_pass() {
+        let mut results = RunResults::new();
+        let mut scenario = ScenarioResult::new(PathBuf::from("./scenarios/boot.json"), 1);
+        scenario.push(CommandResult {
+            index: 0,
+            description: None,
+            verdict: Verdict::Pass,
+            expected: None,
+            received: None,
+            elapsed: Duration::from_millis(20),
+            latency: None,
+            timeout: None,
+            measurements: Vec::new(),
+            attempts: Vec::new(),
+            error: None,
+        });
+        scenario.verdict = Verdict::Mismatch;
+        scenario.error = Some(String::from("Received \"panic\" outside of any command"));
+        results.push(scenario);
+
+        let xml = render(&results).expect("Failed to render report");
+
+        assert!(xml.contains("tests=\"2\" failures=\"1\""));
+        assert!(
+            xml.contains(
+                "<testcase name=\"outside of commands\" classname=\"boot\" time=\"0.000\">"
+            )
+        );
+        assert!(xml.contains(
+            "<failure type=\"mismatch\" message=\"Received &quot;panic&quot; outside of any command\">"
+        ));
+    }
+
+    #[test]
+    fn render_run_error_pass() {
+        let mut results = RunResults::new();
+        results.abort(
+            Outcome::ConnectionError,
+            String::from("Failed to open connection: Connection refused"),
+        );
+
+        let xml = render(&results).expect("Failed to render report");
+
+        assert!(xml.contains(
+            "<testsuites name=\"scenario-runner\" tests=\"1\" failures=\"0\" errors=\"1\" skipped=\"0\">"
+        ));
+        assert!(xml.contains(
+            "<error type=\"run_error\" message=\"Failed to open connection: Connection refused\"/>"
+        ));
+    }
+
+    #[test]
+    fn write_report_pass() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+
+        let report_path =
+            write_report(&sample_results(), temp_dir.path()).expect("Failed to write report");
+        let written = fs::read_to_string(&report_path).expect("Failed to read written report");
+
+        assert_eq!(
+            report_path
+                .extension()
+                .and_then(|extension| extension.to_str()),
+            Some("xml")
+        );
+        assert!(written.starts_with("<?xml"));
+        assert!(written.ends_with("</testsuites>\n"));
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Tx,
+    Rx,
+    Stderr,
+    Rejected,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Tx => write!(f, "TX"),
+            Direction::Rx => write!(f, "RX"),
+            Direction::Stderr => write!(f, "ERR"),
+            Direction::Rejected => write!(f, "BAD"),
+        }
+    }
+}
+
+pub struct Transcript {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl Transcript {
+    pub fn create(results_location: &Path, started: &DateTime<Local>) -> Result<Self> {
+        fs::create_dir_all(results_location)?;
+        let path = results_location.join(format!(
+            "transcript_{}.log",
+            started.format("%Y%m%d_%H%M%S")
+        ));
+        let writer = BufWriter::new(File::create(&path)?);
+        Ok(Transcript { path, writer })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Flushed on every frame so the transcript survives the run being killed mid-scenario
+    pub fn record(
+        &mut self,
+        direction: Direction,
+        timestamp: &DateTime<Local>,
+        data: &[u8],
+    ) -> Result<()> {
+        writeln!(self.writer, "{}", format_frame(direction, timestamp, data))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn format_frame(direction: Direction, timestamp: &DateTime<Local>, data: &[u8]) -> String {
+    format!(
+        "{} {} {:>5} bytes | hex: {} | text: \"{}\"",
+        timestamp.format("%Y-%m-%dT%H:%M:%S%.6f%:z"),
+        direction,
+        data.len(),
+        hex::encode(data),
+        String::from_utf8_lossy(data).escape_debug()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    #[test]
+    fn format_frame_pass() {
+        let timestamp = Local
+            .with_ymd_and_hms(2025, 6, 1, 12, 30, 45)
+            .single()
+            .expect("Failed to build timestamp");
+
+        let line = format_frame(Direction::Rx, &timestamp, b"OK\r\n\xff");
+
+        assert!(line.starts_with("2025-06-01T12:30:45.000000"));
+        assert!(line.ends_with(" RX     5 bytes | hex: 4f4b0d0aff | text: \"OK\\r\\n\u{fffd}\""));
+    }
+
+    #[test]
+    fn record_pass() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let started = Local::now();
+        let mut transcript =
+            Transcript::create(temp_dir.path(), &started).expect("Failed to create transcript");
+
+        transcript
+            .record(Direction::Tx, &started, b"PING")
+            .expect("Failed to record TX frame");
+        transcript
+            .record(Direction::Rx, &started, b"PONG")
+            .expect("Failed to record RX frame");
+        transcript
+            .record(Direction::Rejected, &started, b"\xaa")
+            .expect("Failed to record rejected frame");
+
+        let written =
+            fs::read_to_string(transcript.path()).expect("Failed to read back transcript");
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains(" TX     4 bytes | hex: 50494e47 | text: \"PING\""));
+        assert!(lines[1].contains(" RX     4 bytes | hex: 504f4e47 | text: \"PONG\""));
+        assert!(lines[2].contains(" BAD     1 bytes | hex: aa |"));
+    }
+
+    #[test]
+    fn create_fail_location_is_file() {
+        let temp_dir = tempdir().expect("Failed to create temp dir");
+        let blocking_file = temp_dir.path().join("results");
+        File::create(&blocking_file).expect("Failed to create blocking file");
+
+        assert!(
+            Transcript::create(&blocking_file, &Local::now()).is_err(),
+            "Somehow created a transcript inside a file"
+        );
+    }
+}
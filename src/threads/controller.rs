@@ -1,15 +1,28 @@
-use anyhow::{Result, bail};
+use anyhow::{Result, anyhow, bail};
 use chrono::{DateTime, Local};
 use crossbeam::channel::{self, Receiver, Sender};
-use log::info;
+use log::{error, info, trace, warn};
 use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
 use std::thread;
 use std::time::Duration;
 
 use crate::connection::Communicate;
+use crate::connection::framing::{self, Framer};
+use crate::connection::process::Connection as ProcessConnection;
+#[cfg(unix)]
+use crate::connection::pty::Connection as PtyConnection;
 use crate::connection::tcp::Connection as TcpConnection;
-use crate::connection::usb::Connection as UsbConnection;
-use crate::interaction::config::{Config, ConnectionType};
+use crate::connection::udp::Connection as UdpConnection;
+#[cfg(unix)]
+use crate::connection::unix::Connection as UnixConnection;
+use crate::connection::usb::{Connection as UsbConnection, LineSettings};
+use crate::interaction::command::Repeat;
+use crate::interaction::config::{Config, ConnectionType, Framing, TcpMode};
+use crate::interaction::variables;
+use crate::results::transcript::{Direction, Transcript};
+use crate::results::{Outcome, RunResults, ScenarioResult, json, junit};
 use crate::threads::{handler, runner};
 
 #[allow(dead_code)]
@@ -26,8 +39,22 @@ pub enum Message {
     RunnerSendData {
         data: Vec<u8>,
     },
+    // When the runner actually wrote the data and the bytes that went out once framed
+    RunnerSentData {
+        timestamp: DateTime<Local>,
+        data: Vec<u8>,
+    },
+    RunnerStderrData {
+        timestamp: DateTime<Local>,
+        data: Vec<u8>,
+    },
+    RunnerRejectedData {
+        timestamp: DateTime<Local>,
+        data: Vec<u8>,
+    },
     StartRunnerStream,
     StopRunnerStream,
+    ScenarioFinished(ScenarioResult),
 }
 
 #[derive(Debug, Clone)]
@@ -141,54 +168,279 @@ impl Controller {
     }
 }
 
-pub fn thread(config_file: String) -> Result<()> {
-    let current_config = Config::new(config_file)?;
+pub fn thread(
+    config_file: String,
+    command_line_variables: Vec<(String, String)>,
+    repeat: Repeat,
+) -> Outcome {
+    let mut run_results = RunResults::new();
+    // Without a config to say where results go, they end up next to it
+    let config_location = Path::new(&config_file)
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let current_config = match Config::new(config_file) {
+        Ok(config) => config,
+        Err(msg) => {
+            return abort(
+                run_results,
+                Outcome::ConfigurationError,
+                format!("Failed to load configuration: {msg}"),
+                &config_location,
+            );
+        }
+    };
+
+    let mut transcript =
+        match Transcript::create(&current_config.results_location, &run_results.started) {
+            Ok(transcript) => transcript,
+            Err(msg) => {
+                return abort(
+                    run_results,
+                    Outcome::ConfigurationError,
+                    format!("Failed to create transcript: {msg}"),
+                    &current_config.results_location,
+                );
+            }
+        };
+    info!("Recording traffic to {}", transcript.path().display());
 
     info!("Connecting using specified configuration");
-    let mut opened_connection = open_connection(current_config.connection)?;
+    let mut opened_connection = match open_connection(current_config.connection) {
+        Ok(connection) => connection,
+        Err(msg) => {
+            return abort(
+                run_results,
+                Outcome::ConnectionError,
+                format!("Failed to open connection: {msg}"),
+                &current_config.results_location,
+            );
+        }
+    };
 
+    let framer = build_framer(current_config.framing);
+    let results_location = current_config.results_location.clone();
     let mut hub = Controller::new();
     let handler_endpoint = hub.add_link(Identifier::Handler);
     let runner_endpoint = hub.add_link(Identifier::Runner);
 
-    let handler_handle =
-        thread::spawn(move || handler::thread(current_config.scenarios, handler_endpoint));
+    let definitions = variables::definitions(
+        current_config.variables,
+        variables::environment(env::vars_os()),
+        command_line_variables,
+    );
+    let handler_handle = thread::spawn(move || {
+        handler::thread(
+            current_config.scenarios,
+            definitions,
+            repeat,
+            handler_endpoint,
+        )
+    });
     let runner_handle =
-        thread::spawn(move || runner::thread(&mut opened_connection, runner_endpoint));
+        thread::spawn(move || runner::thread(&mut opened_connection, framer, runner_endpoint));
 
     // Threads should be stopped if Ok is returned, but just in case
-    let _ = match process_messages(&mut hub) {
+    let _ = match process_messages(&mut hub, &mut run_results, &mut transcript) {
         Ok(..) => stop_all_threads(&mut hub),
-        Err(..) => stop_all_threads(&mut hub),
+        Err(..) => {
+            // Losing contact with the threads leaves us in the same spot as losing the connection
+            run_results.escalate(Outcome::ConnectionError);
+            stop_all_threads(&mut hub)
+        }
     };
 
     let _ = handler_handle.join();
     let _ = runner_handle.join();
-    Ok(())
+
+    // The handler may still report the scenario it was on after an early shutdown
+    for message in hub.mailbox.try_receive_all().unwrap_or_default() {
+        if let Message::ScenarioFinished(scenario_result) = message {
+            run_results.push(scenario_result);
+        }
+    }
+    run_results.finish();
+    for statistics in run_results.latency_statistics.iter() {
+        info!(
+            "{} command {} latency over {} runs: min {:?}, mean {:?}, p95 {:?}, max {:?}",
+            statistics.scenario.display(),
+            statistics.index,
+            statistics.samples,
+            statistics.min,
+            statistics.mean,
+            statistics.p95,
+            statistics.max
+        );
+    }
+    if run_results.iterations.len() > 1 {
+        for iteration in run_results.iterations.iter() {
+            info!(
+                "Iteration {} {:?}: {} of {} scenarios and {} of {} commands passed ({:.1}%)",
+                iteration.iteration,
+                iteration.verdict,
+                iteration.scenarios_passed,
+                iteration.scenarios,
+                iteration.commands_passed,
+                iteration.commands,
+                iteration.pass_rate * 100.0
+            );
+        }
+        info!(
+            "{} of {} iterations passed, {:.1}% of commands passed overall",
+            run_results.summary.iterations_passed,
+            run_results.summary.iterations,
+            run_results.summary.pass_rate * 100.0
+        );
+    }
+    write_reports(&run_results, &results_location);
+    info!("Run finished with outcome {:?}", run_results.outcome);
+    run_results.outcome
+}
+
+// Runs that stop before their scenarios still leave reports behind saying why
+fn abort(
+    mut run_results: RunResults,
+    outcome: Outcome,
+    error: String,
+    results_location: &Path,
+) -> Outcome {
+    error!("{error}");
+    run_results.abort(outcome, error);
+    run_results.finish();
+    write_reports(&run_results, results_location);
+    info!("Run finished with outcome {:?}", run_results.outcome);
+    run_results.outcome
+}
+
+fn build_framer(framing: Framing) -> Box<dyn Framer + Send> {
+    match framing {
+        Framing::Delimiter(delimiter) => Box::new(framing::Delimiter::new(delimiter)),
+        Framing::FixedLength(length) => Box::new(framing::FixedLength::new(length)),
+        Framing::IdleGap(gap) => Box::new(framing::IdleGap::new(gap)),
+        Framing::LengthPrefixed(layout) => Box::new(framing::LengthPrefixed::new(layout)),
+        Framing::Slip { encode_sends } => Box::new(framing::Slip::new(encode_sends)),
+        Framing::Cobs { encode_sends } => Box::new(framing::Cobs::new(encode_sends)),
+        Framing::Raw => Box::new(framing::Raw),
+    }
 }
 
 fn open_connection(
     connection_type: ConnectionType,
 ) -> Result<Box<dyn Communicate + Send + 'static>> {
     match connection_type {
-        ConnectionType::Tcp { address, port } => Ok(Box::new(TcpConnection::new(address, port)?)),
-        ConnectionType::Usb { port, baud_rate } => {
-            Ok(Box::new(UsbConnection::new(port, baud_rate)?))
+        ConnectionType::Tcp {
+            address,
+            port,
+            mode: TcpMode::Connect,
+            ..
+        } => Ok(Box::new(TcpConnection::new(address, port)?)),
+        ConnectionType::Tcp {
+            address,
+            port,
+            mode: TcpMode::Listen,
+            accept_timeout,
+            reaccept,
+        } => Ok(Box::new(TcpConnection::listen(
+            address,
+            port,
+            accept_timeout.map(Duration::from_secs),
+            reaccept,
+        )?)),
+        ConnectionType::Usb {
+            port,
+            baud_rate,
+            data_bits,
+            parity,
+            stop_bits,
+            flow_control,
+            read_timeout_ms,
+        } => {
+            let defaults = LineSettings::default();
+            let settings = LineSettings {
+                data_bits: match data_bits {
+                    Some(data_bits) => data_bits
+                        .try_into()
+                        .map_err(|_| anyhow!("Invalid data_bits {data_bits}"))?,
+                    None => defaults.data_bits,
+                },
+                parity: parity.map_or(defaults.parity, Into::into),
+                stop_bits: match stop_bits {
+                    Some(stop_bits) => stop_bits
+                        .try_into()
+                        .map_err(|_| anyhow!("Invalid stop_bits {stop_bits}"))?,
+                    None => defaults.stop_bits,
+                },
+                flow_control: flow_control.map_or(defaults.flow_control, Into::into),
+                read_timeout: read_timeout_ms.map_or(defaults.read_timeout, Duration::from_millis),
+            };
+            Ok(Box::new(UsbConnection::new(port, baud_rate, settings)?))
+        }
+        ConnectionType::Udp {
+            address,
+            port,
+            local_port,
+        } => Ok(Box::new(UdpConnection::new(address, port, local_port)?)),
+        #[cfg(unix)]
+        ConnectionType::Unix { path } => Ok(Box::new(UnixConnection::new(path)?)),
+        ConnectionType::Process {
+            program,
+            args,
+            env,
+            cwd,
+        } => Ok(Box::new(ProcessConnection::new(program, args, env, cwd)?)),
+        #[cfg(unix)]
+        ConnectionType::Pty {
+            program,
+            args,
+            rows,
+            cols,
+        } => Ok(Box::new(PtyConnection::new(program, args, rows, cols)?)),
+    }
+}
+
+fn write_reports(run_results: &RunResults, results_location: &Path) {
+    let writers: [fn(&RunResults, &Path) -> Result<PathBuf>; 2] =
+        [json::write_report, junit::write_report];
+    for writer in writers {
+        match writer(run_results, results_location) {
+            Ok(report_path) => info!("Results written to {}", report_path.display()),
+            Err(msg) => error!("Failed to write results: {msg}"),
         }
     }
 }
 
+// A transcript that can't be written to shouldn't take the whole run down with it
+fn record_frame(
+    transcript: &mut Transcript,
+    direction: Direction,
+    timestamp: &DateTime<Local>,
+    data: &[u8],
+) {
+    if let Err(msg) = transcript.record(direction, timestamp, data) {
+        warn!("Failed to record {direction} frame in transcript: {msg}");
+    }
+}
+
 fn stop_all_threads(hub: &mut Controller) -> Result<()> {
     let _ = hub.send_to_thread(Identifier::Handler, Message::StopRunning);
     let _ = hub.send_to_thread(Identifier::Runner, Message::StopRunning);
     Ok(())
 }
 
-fn process_messages(hub: &mut Controller) -> Result<()> {
+fn process_messages(
+    hub: &mut Controller,
+    run_results: &mut RunResults,
+    transcript: &mut Transcript,
+) -> Result<()> {
     loop {
         let message = hub.wait_on_inbox()?;
         match message {
-            Message::SendError | Message::ReceiveError | Message::StopRunning => {
+            Message::SendError | Message::ReceiveError => {
+                run_results.escalate(Outcome::ConnectionError);
+                stop_all_threads(hub)?;
+                break;
+            }
+            Message::StopRunning => {
                 stop_all_threads(hub)?;
                 break;
             }
@@ -203,12 +455,40 @@ fn process_messages(hub: &mut Controller) -> Result<()> {
             Message::RunnerSendData { .. } => {
                 hub.send_to_thread(Identifier::Runner, message)?;
             }
-            Message::RunnerReceivedData { .. } => {
+            Message::RunnerReceivedData {
+                ref timestamp,
+                ref data,
+                ..
+            } => {
+                record_frame(transcript, Direction::Rx, timestamp, data);
                 let manager = hub.get_thread_manager(Identifier::Runner)?;
                 if manager.get_stream_state() {
                     hub.send_to_thread(Identifier::Handler, message)?;
+                } else {
+                    trace!("Dropping data received while no command was waiting on it");
                 }
-                todo!("Need to add logging here");
+            }
+            Message::RunnerSentData {
+                ref timestamp,
+                ref data,
+            } => {
+                record_frame(transcript, Direction::Tx, timestamp, data);
+                hub.send_to_thread(Identifier::Handler, message)?;
+            }
+            Message::RunnerStderrData { timestamp, data } => {
+                record_frame(transcript, Direction::Stderr, &timestamp, &data);
+            }
+            Message::RunnerRejectedData { timestamp, data } => {
+                warn!("Rejected {} bytes that failed frame validation", data.len());
+                record_frame(transcript, Direction::Rejected, &timestamp, &data);
+            }
+            Message::ScenarioFinished(scenario_result) => {
+                info!(
+                    "{} finished with verdict {:?}",
+                    scenario_result.scenario.display(),
+                    scenario_result.verdict
+                );
+                run_results.push(scenario_result);
             }
         }
     }
@@ -364,7 +644,9 @@ mod tests {
     mod controller_tests {
 
         use super::super::*;
+        use crate::results::Verdict;
         use pretty_assertions::assert_eq;
+        use tempfile::tempdir;
 
         #[test]
         fn add_link_pass() {
@@ -447,6 +729,197 @@ mod tests {
             );
         }
 
+        #[test]
+        fn process_messages_commands_failed() {
+            let mut hub = Controller::new();
+            let handler_manager = hub.add_link(Identifier::Handler);
+            let _runner_manager = hub.add_link(Identifier::Runner);
+            let mut run_results = RunResults::new();
+            let temp_dir = tempdir().expect("Failed to create temp dir");
+            let mut transcript = Transcript::create(temp_dir.path(), &run_results.started)
+                .expect("Failed to create transcript");
+            let mut scenario_result = ScenarioResult::new(PathBuf::from("scenario"), 1);
+            scenario_result.verdict = Verdict::Mismatch;
+
+            handler_manager
+                .send_all(vec![
+                    Message::ScenarioFinished(scenario_result),
+                    Message::StopRunning,
+                ])
+                .expect("Failed to send messages from handler end");
+            process_messages(&mut hub, &mut run_results, &mut transcript)
+                .expect("Failed to process messages");
+
+            assert_eq!(run_results.scenarios.len(), 1);
+            assert_eq!(run_results.outcome, Outcome::CommandsFailed);
+        }
+
+        #[test]
+        fn process_messages_connection_error() {
+            let mut hub = Controller::new();
+            let _handler_manager = hub.add_link(Identifier::Handler);
+            let runner_manager = hub.add_link(Identifier::Runner);
+            let mut run_results = RunResults::new();
+            let temp_dir = tempdir().expect("Failed to create temp dir");
+            let mut transcript = Transcript::create(temp_dir.path(), &run_results.started)
+                .expect("Failed to create transcript");
+
+            runner_manager
+                .send(Message::ReceiveError)
+                .expect("Failed to send message from runner end");
+            process_messages(&mut hub, &mut run_results, &mut transcript)
+                .expect("Failed to process messages");
+
+            assert_eq!(run_results.outcome, Outcome::ConnectionError);
+        }
+
+        #[test]
+        fn process_messages_records_transcript() {
+            let mut hub = Controller::new();
+            let handler_manager = hub.add_link(Identifier::Handler);
+            let runner_manager = hub.add_link(Identifier::Runner);
+            let mut run_results = RunResults::new();
+            let temp_dir = tempdir().expect("Failed to create temp dir");
+            let mut transcript = Transcript::create(temp_dir.path(), &run_results.started)
+                .expect("Failed to create transcript");
+
+            handler_manager
+                .send(Message::RunnerSendData {
+                    data: Vec::from("PING"),
+                })
+                .expect("Failed to send message from handler end");
+            runner_manager
+                .send_all(vec![
+                    Message::RunnerSentData {
+                        timestamp: Local::now(),
+                        data: Vec::from("PING\n"),
+                    },
+                    Message::RunnerReceivedData {
+                        timestamp: Local::now(),
+                        data: Vec::from("PONG"),
+                        data_length: 4,
+                    },
+                    Message::RunnerRejectedData {
+                        timestamp: Local::now(),
+                        data: vec![0xaa, 0x55, 0x00, 0xff],
+                    },
+                    Message::StopRunning,
+                ])
+                .expect("Failed to send messages from runner end");
+            process_messages(&mut hub, &mut run_results, &mut transcript)
+                .expect("Failed to process messages");
+
+            let written =
+                std::fs::read_to_string(transcript.path()).expect("Failed to read back transcript");
+            let lines: Vec<&str> = written.lines().collect();
+            assert_eq!(lines.len(), 3);
+            assert!(
+                lines[0].contains(" TX     5 bytes | hex: 50494e470a |"),
+                "First frame should be the sent one, as framed on the wire"
+            );
+            assert!(
+                lines[1].contains(" RX "),
+                "Second frame should be the received one"
+            );
+            assert!(
+                lines[2].contains(" BAD "),
+                "Third frame should be the rejected one"
+            );
+            assert_eq!(
+                runner_manager
+                    .receive_blocking()
+                    .expect("Runner should have been forwarded the send"),
+                Message::RunnerSendData {
+                    data: Vec::from("PING")
+                }
+            );
+        }
+
+        fn read_report(location: &Path) -> serde_json::Value {
+            let report = std::fs::read_dir(location)
+                .expect("Failed to list results location")
+                .map(|entry| entry.expect("Failed to read directory entry").path())
+                .find(|path| {
+                    path.extension()
+                        .is_some_and(|extension| extension == "json")
+                })
+                .expect("No JSON report was written");
+            serde_json::from_str(
+                &std::fs::read_to_string(report).expect("Failed to read back report"),
+            )
+            .expect("Report was not valid JSON")
+        }
+
+        #[test]
+        fn thread_missing_config_fail() {
+            let temp_dir = tempdir().expect("Failed to create temp dir");
+            let config_file = temp_dir.path().join("does_not_exist.json");
+
+            assert_eq!(
+                thread(
+                    config_file.to_string_lossy().into_owned(),
+                    Vec::new(),
+                    Repeat::Times(1)
+                ),
+                Outcome::ConfigurationError
+            );
+            let report = read_report(temp_dir.path());
+            assert_eq!(report["outcome"], "configuration_error");
+            assert_eq!(
+                report["error"],
+                "Failed to load configuration: Specified Config file does not exist"
+            );
+        }
+
+        #[test]
+        fn thread_connection_refused_fail() {
+            let temp_dir = tempdir().expect("Failed to create temp dir");
+            let results_dir = temp_dir.path().join("results");
+            // Nothing listens on a port once its listener is gone
+            let port = std::net::TcpListener::bind("127.0.0.1:0")
+                .expect("Failed to reserve a port")
+                .local_addr()
+                .expect("Failed to get reserved port")
+                .port();
+            let config_file = temp_dir.path().join("config.json");
+            std::fs::write(
+                &config_file,
+                format!(
+                    r#"{{ "results_location": {:?}, "connection": {{ "type": "Tcp", "address": "127.0.0.1", "port": {port} }}, "scenarios": [] }}"#,
+                    results_dir.display().to_string()
+                ),
+            )
+            .expect("Failed to write config");
+
+            assert_eq!(
+                thread(
+                    config_file.to_string_lossy().into_owned(),
+                    Vec::new(),
+                    Repeat::Times(1)
+                ),
+                Outcome::ConnectionError
+            );
+            let report = read_report(&results_dir);
+            assert_eq!(report["outcome"], "connection_error");
+            assert!(
+                report["error"]
+                    .as_str()
+                    .is_some_and(|error| error.starts_with("Failed to open connection: ")),
+                "Report didn't say why the run stopped: {}",
+                report["error"]
+            );
+            assert!(
+                std::fs::read_dir(&results_dir)
+                    .expect("Failed to list results location")
+                    .any(|entry| entry
+                        .expect("Failed to read directory entry")
+                        .path()
+                        .extension()
+                        .is_some_and(|extension| extension == "xml")),
+                "No JUnit report was written"
+            );
+        }
+
         #[test]
         fn get_thread_manager_fail() {
             let mut hub = Controller::new();
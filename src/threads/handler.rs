@@ -1,111 +1,588 @@
-use crate::interaction::command::{self, Sendable, parse_scenario};
+use crate::interaction::command::{
+    self, Command, ExpectOrder, Repeat, Scenario, Step, parse_scenario,
+};
+use crate::interaction::matcher::{Expectation, Pattern};
+use crate::interaction::variables::{Definitions, Variables};
+use crate::results::{
+    AttemptResult, CommandResult, MeasurementResult, Payload, ScenarioResult, Verdict,
+};
 use crate::threads::controller::{ItcManager, Message};
+use anyhow::{Result, bail};
 use log::{debug, error, info, trace, warn};
 use std::path::PathBuf;
 use std::thread;
 use std::time::{Duration, Instant};
 
-pub fn thread(scenarios: Vec<PathBuf>, manager: ItcManager) {
+pub fn thread(
+    scenarios: Vec<PathBuf>,
+    definitions: Definitions,
+    repeat: Repeat,
+    manager: ItcManager,
+) {
     info!("Starting Scenario Handler Thread!");
-    'scenario_loop: for scenario in scenarios {
-        if !scenario.is_file() {
-            warn!("{} does not exist, skipping", scenario.display());
-            continue;
+    let started = Instant::now();
+    let mut completed = 0;
+    'run_loop: while repeat.keeps_going(completed, started) {
+        let iteration = completed + 1;
+        if repeat != Repeat::Times(1) {
+            info!("Starting iteration {iteration}");
         }
-
-        let scenario_commands = match parse_scenario(&scenario) {
-            Ok(commands) => commands,
-            Err(msg) => {
-                warn!("{} could not be parsed, skipping", scenario.display());
-                trace!("Error while parsing: {msg}");
+        let mut ran_commands = false;
+        for scenario in scenarios.iter() {
+            if !scenario.is_file() {
+                warn!("{} does not exist, skipping", scenario.display());
+                let _ = manager.send(Message::ScenarioFinished(ScenarioResult::with_error(
+                    scenario.clone(),
+                    iteration,
+                    Verdict::Skipped,
+                    String::from("Scenario file does not exist"),
+                )));
                 continue;
             }
-        };
 
-        for (cnt, command) in scenario_commands.into_iter().enumerate() {
-            match command.command {
-                command::Destination::Connection {
-                    send,
-                    expect_exact,
-                    expect_prefix,
-                    timeout,
-                    delay,
-                } => {
-                    let data = match send {
-                        Sendable::Hex { data } => data,
-                        Sendable::Text { data } => data,
-                    };
-                    thread::sleep(delay);
-                    let start_sequence =
-                        vec![Message::RunnerSendData { data }, Message::StopRunnerStream];
-                    trace!("Sending command {} in scenario {}", cnt, scenario.display());
-                    if manager.send_all(start_sequence).is_err() {
-                        warn!(
-                            "Command {} in {} could not be sent, skipping",
-                            cnt,
-                            scenario.display()
+            let parsed = match parse_scenario(scenario, &definitions) {
+                Ok(parsed) => parsed,
+                Err(msg) => {
+                    warn!("{} could not be parsed, skipping", scenario.display());
+                    trace!("Error while parsing: {msg:#}");
+                    let _ = manager.send(Message::ScenarioFinished(ScenarioResult::with_error(
+                        scenario.clone(),
+                        iteration,
+                        Verdict::ParseError,
+                        format!("{msg:#}"),
+                    )));
+                    continue;
+                }
+            };
+
+            let mut scenario_result = ScenarioResult::new(scenario.clone(), iteration);
+            let mut variables = Variables::new();
+            // Leftovers from the previous scenario aren't checked against this one's forbidden patterns
+            let mut connection_lost = check_forbidden(&manager, &[], &mut scenario_result).is_err();
+            // Forbidden patterns are watched for between commands too, so data keeps coming in
+            let watch_scenario = !parsed.forbidden.is_empty();
+            if watch_scenario {
+                let _ = manager.send(Message::StartRunnerStream);
+            }
+            run_steps(
+                &manager,
+                &parsed,
+                &parsed.steps,
+                &mut scenario_result,
+                &mut variables,
+                &mut connection_lost,
+            );
+
+            ran_commands |= scenario_result
+                .commands
+                .iter()
+                .any(|command_result| command_result.verdict != Verdict::Skipped);
+            if watch_scenario {
+                // Whatever came in after the last command still counts against the scenario
+                if !connection_lost
+                    && check_forbidden(&manager, &parsed.forbidden, &mut scenario_result).is_err()
+                {
+                    connection_lost = true;
+                }
+                let _ = manager.send(Message::StopRunnerStream);
+            }
+            let _ = manager.send(Message::ScenarioFinished(scenario_result));
+            if connection_lost {
+                break 'run_loop;
+            }
+        }
+        completed += 1;
+        // Nothing would change next time around, a --duration run would just pile up results
+        if !ran_commands {
+            warn!("Iteration {iteration} didn't run any commands, stopping the run");
+            break;
+        }
+    }
+    let _ = manager.send(Message::StopRunning);
+    info!("Stopping Scenario Handler Thread!");
+}
+
+fn run_steps(
+    manager: &ItcManager,
+    scenario: &Scenario,
+    steps: &[Step],
+    scenario_result: &mut ScenarioResult,
+    variables: &mut Variables,
+    connection_lost: &mut bool,
+) {
+    for step in steps {
+        match step {
+            Step::Command(index) => {
+                let command = &scenario.commands[*index];
+                if *connection_lost {
+                    scenario_result.push(skipped_result(*index, command.description.clone()));
+                    continue;
+                }
+
+                trace!(
+                    "Sending command {index} in scenario {}",
+                    scenario_result.scenario.display()
+                );
+                match run_command(
+                    manager,
+                    *index,
+                    command.clone(),
+                    &scenario.forbidden,
+                    scenario_result,
+                    variables,
+                ) {
+                    Ok(command_result) => scenario_result.push(command_result),
+                    Err(msg) => {
+                        error!(
+                            "{msg}, skipping remaining commands in {}",
+                            scenario_result.scenario.display()
                         );
+                        scenario_result.push(skipped_result(*index, command.description.clone()));
+                        *connection_lost = true;
                     }
-                    let start_time = Instant::now();
-                    while Instant::now() - start_time < timeout {
-                        let elapsed_time = Instant::now() - start_time;
-                        let remaining_time = timeout
-                            .checked_sub(elapsed_time)
+                }
+            }
+            Step::Repeat { repeat, steps } => {
+                let started = Instant::now();
+                let mut completed = 0;
+                // Without a connection the rest of the block is only marked skipped the once
+                while repeat.keeps_going(completed, started) && !(*connection_lost && completed > 0)
+                {
+                    trace!("Repeating block, pass {}", completed + 1);
+                    run_steps(
+                        manager,
+                        scenario,
+                        steps,
+                        scenario_result,
+                        variables,
+                        connection_lost,
+                    );
+                    completed += 1;
+                }
+            }
+        }
+    }
+}
+
+fn skipped_result(index: usize, description: Option<String>) -> CommandResult {
+    CommandResult {
+        index,
+        description,
+        verdict: Verdict::Skipped,
+        expected: None,
+        received: None,
+        elapsed: Duration::from_secs(0),
+        latency: None,
+        timeout: None,
+        measurements: Vec::new(),
+        attempts: Vec::new(),
+        error: None,
+    }
+}
+
+fn run_command(
+    manager: &ItcManager,
+    index: usize,
+    command: Command,
+    forbidden: &[Pattern],
+    scenario_result: &mut ScenarioResult,
+    variables: &mut Variables,
+) -> Result<CommandResult> {
+    match command.command {
+        command::Destination::Connection {
+            send,
+            expect,
+            expect_order,
+            expect_absent,
+            max_latency,
+            min_latency,
+            timeout,
+            delay,
+            retries,
+            retry_delay,
+            repeat_until,
+        } => {
+            let mut command_result = CommandResult {
+                index,
+                description: command.description,
+                verdict: Verdict::Pass,
+                expected: None,
+                received: None,
+                elapsed: Duration::from_secs(0),
+                latency: None,
+                timeout: None,
+                measurements: Vec::new(),
+                attempts: Vec::new(),
+                error: None,
+            };
+            // A value an earlier command failed to capture leaves nothing sensible to send
+            let data = match send.render(variables) {
+                Ok(data) => data,
+                Err(msg) => {
+                    warn!("Skipping command {index}: {msg}");
+                    command_result.verdict = Verdict::Skipped;
+                    command_result.error = Some(msg.to_string());
+                    return Ok(command_result);
+                }
+            };
+
+            thread::sleep(delay);
+            // Anything still queued came in before this command was sent, so it's charged to the gap
+            check_forbidden(manager, forbidden, scenario_result)?;
+
+            let start_time = Instant::now();
+            let deadline = repeat_until.map(|repeat_until| start_time + repeat_until);
+            let fresh_result = command_result;
+            let mut attempts = Vec::new();
+            let mut command_result = loop {
+                let mut command_result = fresh_result.clone();
+                let mut expect = expect.clone();
+                let mut violated = false;
+                let start_sequence = vec![
+                    Message::StartRunnerStream,
+                    Message::RunnerSendData { data: data.clone() },
+                ];
+                manager.send_all(start_sequence)?;
+                let attempt_time = Instant::now();
+                let mut sent_at = None;
+                // Polling never waits past the deadline, even when the timeout would allow it
+                let attempt_timeout = match deadline {
+                    Some(deadline) => timeout.min(deadline.saturating_duration_since(attempt_time)),
+                    None => timeout,
+                };
+
+                if !expect.is_empty() || !expect_absent.is_empty() {
+                    let total = expect.len();
+                    command_result.timeout = Some(timeout);
+                    if let Some(first) = expect.first() {
+                        command_result.expected = Some(expected_payload(first));
+                        command_result.verdict = Verdict::Timeout;
+                    }
+                    loop {
+                        let remaining_time = attempt_timeout
+                            .checked_sub(attempt_time.elapsed())
                             .unwrap_or(Duration::from_secs(0));
 
                         if remaining_time.is_zero() {
-                            trace!(
-                                "Command timed out, expected prefix or response was not received"
-                            );
+                            if !expect.is_empty() {
+                                trace!(
+                                    "Command timed out, expected prefix or response was not received"
+                                );
+                                if total > 1 {
+                                    command_result.error = Some(format!(
+                                        "Saw {} of {total} expected responses",
+                                        total - expect.len()
+                                    ));
+                                }
+                            }
                             break;
                         }
 
-                        if let Ok(message) = manager.receive_timeout(remaining_time) {
-                            if !expect_prefix.is_empty() {
-                                match message {
-                                    Message::RunnerReceivedData { data, .. } => {
-                                        if data.starts_with(&expect_prefix) {
-                                            if data == expect_exact {
-                                                trace!("Found exact response");
+                        let Ok(message) = manager.receive_timeout(remaining_time) else {
+                            continue;
+                        };
+                        match message {
+                            Message::RunnerSentData { timestamp, .. } => sent_at = Some(timestamp),
+                            Message::RunnerReceivedData {
+                                timestamp, data, ..
+                            } => {
+                                if let Some(pattern) = find_match(forbidden, &data) {
+                                    record_violation(
+                                        &mut command_result,
+                                        "forbidden",
+                                        pattern,
+                                        &data,
+                                    );
+                                    violated = true;
+                                    break;
+                                }
+                                if let Some(pattern) = find_match(&expect_absent, &data) {
+                                    record_violation(
+                                        &mut command_result,
+                                        "expect_absent",
+                                        pattern,
+                                        &data,
+                                    );
+                                    violated = true;
+                                    break;
+                                }
+                                // Everything expected has turned up, only watching for absent patterns now
+                                if expect.is_empty() {
+                                    continue;
+                                }
+
+                                // In order only the next expectation is in the running
+                                let candidates = match expect_order {
+                                    ExpectOrder::InOrder => 1,
+                                    ExpectOrder::AnyOrder => expect.len(),
+                                };
+                                if let Some(position) = expect[..candidates]
+                                    .iter()
+                                    .position(|expectation| expectation.matches(&data))
+                                {
+                                    trace!("Found expected response");
+                                    let expectation = expect.remove(position);
+                                    if let Err(msg) = capture_values(&expectation, &data, variables)
+                                    {
+                                        debug!("{msg}");
+                                        command_result.verdict = Verdict::Mismatch;
+                                        command_result.error = Some(msg.to_string());
+                                        command_result.received =
+                                            Some(Payload::from(data.as_slice()));
+                                        break;
+                                    }
+                                    if let Some(msg) =
+                                        measure_values(&expectation, &data, &mut command_result)
+                                    {
+                                        debug!("{msg}");
+                                        command_result.verdict = Verdict::Mismatch;
+                                        command_result.error = Some(msg);
+                                        command_result.received =
+                                            Some(Payload::from(data.as_slice()));
+                                        break;
+                                    }
+                                    match expect.first() {
+                                        Some(next) => {
+                                            command_result.expected = Some(expected_payload(next));
+                                        }
+                                        None => {
+                                            // Falls back to when the send was queued if the runner never said
+                                            let latency = match sent_at {
+                                                Some(sent_at) => (timestamp - sent_at)
+                                                    .to_std()
+                                                    .unwrap_or_default(),
+                                                None => attempt_time.elapsed(),
+                                            };
+                                            debug!("Response took {latency:?} of {timeout:?}");
+                                            command_result.latency = Some(latency);
+                                            command_result.received =
+                                                Some(Payload::from(data.as_slice()));
+                                            if let Some(msg) =
+                                                check_latency(latency, min_latency, max_latency)
+                                            {
+                                                debug!("{msg}");
+                                                command_result.verdict = Verdict::Mismatch;
+                                                command_result.error = Some(msg);
                                                 break;
-                                            } else {
-                                                debug!(
-                                                    "Found expected prefix, but response didn't match"
-                                                );
-                                                trace!(
-                                                    "Response Received: {}, Response Expected: {}",
-                                                    String::from_utf8_lossy(data.as_ref()),
-                                                    String::from_utf8_lossy(expect_exact.as_ref())
-                                                );
+                                            }
+                                            command_result.verdict = Verdict::Pass;
+                                            if expect_absent.is_empty() {
                                                 break;
                                             }
                                         }
                                     }
-                                    Message::SendError | Message::ReceiveError => {
-                                        error!(
-                                            "Something went wrong with the connection, shutting down program"
-                                        );
-                                        break 'scenario_loop;
-                                    }
-                                    _ => {
-                                        warn!("Received something unexpected from runner")
+                                } else if let Some(expectation) = expect[..candidates]
+                                    .iter()
+                                    .find(|expectation| expectation.picks_out(&data))
+                                {
+                                    debug!("Found expected prefix, but response didn't match");
+                                    trace!(
+                                        "Response Received: {}, Response Expected: {}",
+                                        String::from_utf8_lossy(data.as_ref()),
+                                        expected_payload(expectation).text
+                                    );
+                                    command_result.expected = Some(expected_payload(expectation));
+                                    command_result.received = Some(Payload::from(data.as_slice()));
+                                    command_result.verdict = Verdict::Mismatch;
+                                    // Says which fields were off rather than leaving it to a byte comparison
+                                    let mismatches = expectation.json_mismatches(&data);
+                                    if !mismatches.is_empty() {
+                                        command_result.error = Some(mismatches.join("; "));
                                     }
+                                    break;
                                 }
                             }
+                            Message::SendError | Message::ReceiveError | Message::StopRunning => {
+                                bail!("Something went wrong with the connection");
+                            }
+                            _ => {
+                                warn!("Received something unexpected from runner")
+                            }
                         }
                     }
                 }
+
+                attempts.push(AttemptResult {
+                    verdict: command_result.verdict,
+                    received: command_result.received.clone(),
+                    elapsed: attempt_time.elapsed(),
+                    error: command_result.error.clone(),
+                });
+                // Retries only cover a response that never came, polling also covers the wrong one
+                let retry = match deadline {
+                    Some(deadline) => {
+                        !command_result.verdict.is_pass()
+                            && !violated
+                            && Instant::now() + retry_delay < deadline
+                    }
+                    None => {
+                        command_result.verdict == Verdict::Timeout
+                            && attempts.len() <= retries as usize
+                    }
+                };
+                if !retry {
+                    break command_result;
+                }
+                debug!(
+                    "Attempt {} of command {index} ended with {:?}, trying again",
+                    attempts.len(),
+                    command_result.verdict
+                );
+                thread::sleep(retry_delay);
             };
+            if retries > 0 || deadline.is_some() {
+                command_result.attempts = attempts;
+            }
+            command_result.elapsed = start_time.elapsed();
+            if forbidden.is_empty() {
+                manager.send(Message::StopRunnerStream)?;
+            }
+            Ok(command_result)
         }
     }
-    let _ = manager.send(Message::StopRunning);
-    info!("Stopping Scenario Handler Thread!");
+}
+
+// The pattern or JSON assertions stand in for the expected response when there's nothing exact
+fn expected_payload(expectation: &Expectation) -> Payload {
+    if !expectation.exact.is_empty() {
+        return Payload::from(&expectation.exact);
+    }
+    match &expectation.regex {
+        Some(pattern) => Payload::from(pattern.as_str().as_bytes()),
+        None if !expectation.json.is_empty() => {
+            let assertions: Vec<String> = expectation
+                .json
+                .iter()
+                .map(|assertion| assertion.to_string())
+                .collect();
+            Payload::from(assertions.join("; ").as_bytes())
+        }
+        None => Payload::from(&expectation.exact),
+    }
+}
+
+fn check_latency(
+    latency: Duration,
+    min_latency: Option<Duration>,
+    max_latency: Option<Duration>,
+) -> Option<String> {
+    if let Some(max_latency) = max_latency
+        && latency > max_latency
+    {
+        return Some(format!(
+            "Response took {latency:?}, more than the max_latency of {max_latency:?}"
+        ));
+    }
+    if let Some(min_latency) = min_latency
+        && latency < min_latency
+    {
+        return Some(format!(
+            "Response took {latency:?}, less than the min_latency of {min_latency:?}"
+        ));
+    }
+    None
+}
+
+fn find_match<'a>(patterns: &'a [Pattern], data: &[u8]) -> Option<&'a Pattern> {
+    patterns.iter().find(|pattern| pattern.is_match(data))
+}
+
+// Empties the queue, recording the first forbidden frame on the scenario rather than any one command
+fn check_forbidden(
+    manager: &ItcManager,
+    forbidden: &[Pattern],
+    scenario_result: &mut ScenarioResult,
+) -> Result<()> {
+    for message in manager.try_receive_all()? {
+        match message {
+            Message::StopRunning => bail!("Handler was told to stop"),
+            Message::RunnerReceivedData { data, .. } => {
+                if let Some(pattern) = find_match(forbidden, &data) {
+                    let msg = format!(
+                        "Received {:?} outside of any command, matching forbidden pattern {:?}",
+                        String::from_utf8_lossy(&data),
+                        pattern.as_str()
+                    );
+                    warn!("{msg}");
+                    if scenario_result.verdict.is_pass() {
+                        scenario_result.verdict = Verdict::Mismatch;
+                    }
+                    scenario_result.error.get_or_insert(msg);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn record_violation(
+    command_result: &mut CommandResult,
+    field: &str,
+    pattern: &Pattern,
+    data: &[u8],
+) {
+    let msg = format!(
+        "Received a response matching {field} pattern {:?}",
+        pattern.as_str()
+    );
+    warn!("{msg}");
+    command_result.verdict = Verdict::Mismatch;
+    command_result.received = Some(Payload::from(data));
+    command_result.error = Some(msg);
+}
+
+fn capture_values(expectation: &Expectation, data: &[u8], variables: &mut Variables) -> Result<()> {
+    for value in &expectation.capture {
+        match value.extract(data, expectation.regex.as_ref()) {
+            Some(captured) => {
+                trace!("Captured {} from the response", value.name);
+                variables.insert(value.name.clone(), captured);
+            }
+            None => bail!("Could not capture {} from the response", value.name),
+        }
+    }
+    Ok(())
+}
+
+// Every value is recorded before any verdict, so one out of bounds doesn't hide the rest
+fn measure_values(
+    expectation: &Expectation,
+    data: &[u8],
+    command_result: &mut CommandResult,
+) -> Option<String> {
+    let mut failures = Vec::new();
+    for measurement in &expectation.measure {
+        let name = &measurement.name;
+        let Some(value) = measurement.extract(data, expectation.regex.as_ref()) else {
+            failures.push(format!("Could not measure {name} from the response"));
+            continue;
+        };
+        trace!("Measured {name} as {value}");
+        let mut verdict = Verdict::Pass;
+        if let Some(bounds) = measurement.bounds
+            && !bounds.contains(value)
+        {
+            failures.push(format!("{name} measured {value}, expected {bounds}"));
+            verdict = Verdict::Mismatch;
+        }
+        command_result.measurements.push(MeasurementResult {
+            name: name.clone(),
+            value,
+            min: measurement.bounds.and_then(|bounds| bounds.min()),
+            max: measurement.bounds.and_then(|bounds| bounds.max()),
+            verdict,
+        });
+    }
+    (!failures.is_empty()).then(|| failures.join("; "))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::interaction::matcher::MaskedBytes;
     use crossbeam::channel;
+    use pretty_assertions::assert_eq;
     use std::{io::Write, path::PathBuf, vec};
     use tempfile::NamedTempFile;
 
@@ -122,7 +599,14 @@ mod tests {
     fn thread_no_scenarios() {
         let (unit_channel, thread_channel) = setup();
 
-        let handle = thread::spawn(move || thread(Vec::new(), thread_channel));
+        let handle = thread::spawn(move || {
+            thread(
+                Vec::new(),
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
         let received_message = unit_channel
             .receive_timeout(Duration::from_secs(5))
             .expect("Should've received a runner stop message");
@@ -138,11 +622,31 @@ mod tests {
     fn thread_scenario_not_a_file() {
         let (unit_channel, thread_channel) = setup();
 
-        let handle = thread::spawn(move || thread(vec![PathBuf::from(".")], thread_channel));
+        let handle = thread::spawn(move || {
+            thread(
+                vec![PathBuf::from(".")],
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
         let received_message = unit_channel
             .receive_timeout(Duration::from_secs(5))
-            .expect("Should've received a runner stop message");
+            .expect("Should've received a scenario finished message");
+        assert!(
+            matches!(
+                received_message,
+                Message::ScenarioFinished(ScenarioResult {
+                    verdict: Verdict::Skipped,
+                    ..
+                })
+            ),
+            "Unexpectedly received something else"
+        );
 
+        let received_message = unit_channel
+            .receive_timeout(Duration::from_secs(5))
+            .expect("Should've received a runner stop message");
         assert!(
             matches!(received_message, Message::StopRunning),
             "Unexpectedly received something else"
@@ -150,6 +654,34 @@ mod tests {
         assert!(handle.join().is_ok(), "Thread joined with fail")
     }
 
+    #[test]
+    fn thread_repeat_stops_without_commands() {
+        let (unit_channel, thread_channel) = setup();
+
+        let handle = thread::spawn(move || {
+            thread(
+                vec![PathBuf::from(".")],
+                Definitions::new(),
+                Repeat::For(Duration::from_secs(3600)),
+                thread_channel,
+            )
+        });
+        let mut finished = 0;
+        loop {
+            match unit_channel
+                .receive_timeout(Duration::from_secs(5))
+                .expect("Should've stopped rather than repeating for an hour")
+            {
+                Message::ScenarioFinished(_) => finished += 1,
+                Message::StopRunning => break,
+                _ => {}
+            }
+        }
+
+        assert_eq!(finished, 1);
+        assert!(handle.join().is_ok(), "Thread joined with fail")
+    }
+
     #[test]
     fn thread_invalid_scenario() {
         let (unit_channel, thread_channel) = setup();
@@ -176,15 +708,1060 @@ mod tests {
             .expect("Failed to write dummy scenario");
         let scenarios = vec![temp_file.path().to_path_buf()];
 
-        let handle = thread::spawn(move || thread(scenarios, thread_channel));
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
         let received_message = unit_channel
             .receive_timeout(Duration::from_secs(5))
-            .expect("Should've received a runner stop message");
+            .expect("Should've received a scenario finished message");
+        assert!(
+            matches!(
+                received_message,
+                Message::ScenarioFinished(ScenarioResult {
+                    verdict: Verdict::ParseError,
+                    error: Some(..),
+                    ..
+                })
+            ),
+            "Unexpectedly received something else"
+        );
 
+        let received_message = unit_channel
+            .receive_timeout(Duration::from_secs(5))
+            .expect("Should've received a runner stop message");
         assert!(
             matches!(received_message, Message::StopRunning),
             "Unexpectedly received something else"
         );
         assert!(handle.join().is_ok(), "Thread joined with fail")
     }
+
+    fn scenario_file(json: &str) -> NamedTempFile {
+        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        temp_file
+            .write_all(json.as_bytes())
+            .expect("Failed to write dummy scenario");
+        temp_file
+    }
+
+    // Plays the part of the controller and runner, answering each send with the frames at its position
+    // and stamping them the given latency after the write, sends past the end go unanswered
+    fn respond(
+        unit_channel: ItcManager,
+        replies: &[&[&[u8]]],
+        latency: Duration,
+    ) -> ScenarioResult {
+        let mut sends = 0;
+        loop {
+            let message = unit_channel
+                .receive_timeout(Duration::from_secs(5))
+                .expect("Handler went quiet");
+            match message {
+                Message::RunnerSendData { data } => {
+                    let frames = replies.get(sends).copied().unwrap_or_default();
+                    sends += 1;
+                    let written = chrono::Local::now();
+                    let mut responses = vec![Message::RunnerSentData {
+                        timestamp: written,
+                        data,
+                    }];
+                    responses.extend(frames.iter().map(|frame| Message::RunnerReceivedData {
+                        timestamp: written + latency,
+                        data: Vec::from(*frame),
+                        data_length: frame.len(),
+                    }));
+                    unit_channel
+                        .send_all(responses)
+                        .expect("Failed to send response");
+                }
+                Message::ScenarioFinished(scenario_result) => return scenario_result,
+                _ => {}
+            }
+        }
+    }
+
+    const HELLO_SCENARIO: &str = r#"
+        [
+            {
+                "command": {
+                    "destination": "Connection",
+                    "send": { "type": "Text", "data": "Hello" },
+                    "expect_prefix": "Hello",
+                    "expect_exact": "Hello World!",
+                    "timeout": 5
+                },
+                "description": "Say hello"
+            }
+        ]
+        "#;
+
+    #[test]
+    fn thread_repeat_pass() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(
+            r#"
+            [
+                {
+                    "repeat": {
+                        "times": 3,
+                        "commands": [
+                            {
+                                "command": {
+                                    "destination": "Connection",
+                                    "send": { "type": "Text", "data": "Hello" },
+                                    "expect_prefix": "Hello",
+                                    "expect_exact": "Hello World!",
+                                    "timeout": 5
+                                }
+                            }
+                        ]
+                    }
+                },
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "Hello" },
+                        "expect_regex": "World",
+                        "timeout": 5
+                    }
+                }
+            ]
+            "#,
+        );
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(2),
+                thread_channel,
+            )
+        });
+        let reply: &[&[u8]] = &[b"Hello World!"];
+        let replies = [reply; 4];
+        let iterations = [
+            respond(unit_channel.clone(), &replies, Duration::ZERO),
+            respond(unit_channel, &replies, Duration::ZERO),
+        ];
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        for (iteration, scenario_result) in (1..).zip(iterations) {
+            assert_eq!(scenario_result.iteration, iteration);
+            assert_eq!(scenario_result.verdict, Verdict::Pass);
+            assert_eq!(
+                scenario_result
+                    .commands
+                    .iter()
+                    .map(|command_result| command_result.index)
+                    .collect::<Vec<_>>(),
+                vec![0, 0, 0, 1]
+            );
+        }
+    }
+
+    #[test]
+    fn thread_command_pass() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(HELLO_SCENARIO);
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(
+            unit_channel,
+            &[&[b"Unrelated line", b"Hello World!"]],
+            Duration::ZERO,
+        );
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Pass);
+        let command_result = &scenario_result.commands[0];
+        assert_eq!(command_result.description.as_deref(), Some("Say hello"));
+        assert_eq!(command_result.timeout, Some(Duration::from_secs(5)));
+        assert!(
+            command_result
+                .latency
+                .is_some_and(|latency| latency <= command_result.elapsed),
+            "Latency wasn't recorded"
+        );
+        assert_eq!(
+            command_result.received,
+            Some(Payload::from(b"Hello World!".as_slice()))
+        );
+    }
+
+    #[test]
+    fn thread_command_mismatch() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(HELLO_SCENARIO);
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(
+            unit_channel,
+            &[&[b"Unrelated line", b"Hello Moon!"]],
+            Duration::ZERO,
+        );
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Mismatch);
+        assert_eq!(
+            scenario_result.commands[0].expected,
+            Some(Payload::from(b"Hello World!".as_slice()))
+        );
+    }
+
+    #[test]
+    fn thread_command_timeout() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file =
+            scenario_file(&HELLO_SCENARIO.replace(r#""timeout": 5"#, r#""timeout": 1"#));
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(unit_channel, &[], Duration::ZERO);
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Timeout);
+        assert!(scenario_result.commands[0].elapsed >= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn thread_command_regex_pass() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(
+            r#"[{ "command": { "destination": "Connection", "send": { "type": "Text", "data": "Hello" }, "expect_regex": "^Hello W[a-z]+!$", "timeout": 5 } }]"#,
+        );
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(
+            unit_channel,
+            &[&[b"Unrelated line", b"Hello World!"]],
+            Duration::ZERO,
+        );
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Pass);
+        assert_eq!(
+            scenario_result.commands[0].received,
+            Some(Payload::from(b"Hello World!".as_slice()))
+        );
+    }
+
+    #[test]
+    fn thread_command_regex_with_prefix_mismatch() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(
+            r#"[{ "command": { "destination": "Connection", "send": { "type": "Text", "data": "Hello" }, "expect_prefix": "Hello", "expect_regex": "^Hello W[a-z]+!$", "timeout": 5 } }]"#,
+        );
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(
+            unit_channel,
+            &[&[b"Unrelated line", b"Hello 42!"]],
+            Duration::ZERO,
+        );
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Mismatch);
+        assert_eq!(
+            scenario_result.commands[0].expected,
+            Some(Payload::from(b"^Hello W[a-z]+!$".as_slice()))
+        );
+    }
+
+    #[test]
+    fn thread_command_capture_pass() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "GET_ID" },
+                        "expect_regex": "^ID:(?<id>[0-9A-F]+)$",
+                        "capture": { "device_id": { "group": "id" } },
+                        "timeout": 5
+                    }
+                },
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "SET ${device_id}" }
+                    }
+                }
+            ]
+            "#,
+        );
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let mut sent = Vec::new();
+        let scenario_result = loop {
+            let message = unit_channel
+                .receive_timeout(Duration::from_secs(5))
+                .expect("Handler went quiet");
+            match message {
+                Message::RunnerSendData { data } => {
+                    if sent.is_empty() {
+                        unit_channel
+                            .send(Message::RunnerReceivedData {
+                                timestamp: chrono::Local::now(),
+                                data: Vec::from("ID:00C0FFEE"),
+                                data_length: 11,
+                            })
+                            .expect("Failed to send response");
+                    }
+                    sent.push(data);
+                }
+                Message::ScenarioFinished(scenario_result) => break scenario_result,
+                _ => {}
+            }
+        };
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Pass);
+        assert_eq!(sent, vec![Vec::from("GET_ID"), Vec::from("SET 00C0FFEE")]);
+    }
+
+    const EXPECT_LIST_SCENARIO: &str = r#"
+        [
+            {
+                "command": {
+                    "destination": "Connection",
+                    "send": { "type": "Text", "data": "Hello" },
+                    "expect": [
+                        { "prefix": "MODE", "regex": "^MODE (idle|busy)$" },
+                        { "regex": "^T=\\d+$" },
+                        { "prefix": "OK", "exact": "OK" }
+                    ],
+                    "expect_order": "in_order",
+                    "timeout": 5
+                }
+            }
+        ]
+        "#;
+
+    #[test]
+    fn thread_command_expect_in_order_pass() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(EXPECT_LIST_SCENARIO);
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(
+            unit_channel,
+            &[&[b"MODE idle", b"Unrelated line", b"T=21", b"OK"]],
+            Duration::ZERO,
+        );
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Pass);
+        assert_eq!(
+            scenario_result.commands[0].received,
+            Some(Payload::from(b"OK".as_slice()))
+        );
+    }
+
+    #[test]
+    fn thread_command_expect_in_order_timeout() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file =
+            scenario_file(&EXPECT_LIST_SCENARIO.replace(r#""timeout": 5"#, r#""timeout": 1"#));
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(
+            unit_channel,
+            &[&[b"OK", b"T=21", b"MODE busy"]],
+            Duration::ZERO,
+        );
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        let command_result = &scenario_result.commands[0];
+        assert_eq!(command_result.verdict, Verdict::Timeout);
+        assert_eq!(
+            command_result.error.as_deref(),
+            Some("Saw 1 of 3 expected responses")
+        );
+        assert_eq!(
+            command_result.expected,
+            Some(Payload::from(br"^T=\d+$".as_slice()))
+        );
+    }
+
+    #[test]
+    fn thread_command_expect_any_order_pass() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(&EXPECT_LIST_SCENARIO.replace("in_order", "any_order"));
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(
+            unit_channel,
+            &[&[b"OK", b"T=21", b"MODE busy"]],
+            Duration::ZERO,
+        );
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Pass);
+    }
+
+    #[test]
+    fn thread_command_expect_any_order_mismatch() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(&EXPECT_LIST_SCENARIO.replace("in_order", "any_order"));
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(unit_channel, &[&[b"T=21", b"MODE broken"]], Duration::ZERO);
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        let command_result = &scenario_result.commands[0];
+        assert_eq!(command_result.verdict, Verdict::Mismatch);
+        assert_eq!(
+            command_result.received,
+            Some(Payload::from(b"MODE broken".as_slice()))
+        );
+    }
+
+    #[test]
+    fn thread_command_expect_absent_mismatch() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(
+            r#"[{ "command": { "destination": "Connection", "send": { "type": "Text", "data": "Hello" }, "expect_prefix": "Hello", "expect_exact": "Hello World!", "expect_absent": ["ERROR"], "timeout": 5 } }]"#,
+        );
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(
+            unit_channel,
+            &[&[b"Hello World!", b"ERROR: overheated"]],
+            Duration::ZERO,
+        );
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        let command_result = &scenario_result.commands[0];
+        assert_eq!(command_result.verdict, Verdict::Mismatch);
+        assert_eq!(
+            command_result.received,
+            Some(Payload::from(b"ERROR: overheated".as_slice()))
+        );
+        assert_eq!(
+            command_result.error.as_deref(),
+            Some("Received a response matching expect_absent pattern \"ERROR\"")
+        );
+    }
+
+    #[test]
+    fn thread_command_expect_absent_pass() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(
+            r#"[{ "command": { "destination": "Connection", "send": { "type": "Text", "data": "Hello" }, "expect_absent": ["ERROR", "panic"], "timeout": 1 } }]"#,
+        );
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(unit_channel, &[&[b"all good"]], Duration::ZERO);
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Pass);
+        assert!(scenario_result.commands[0].elapsed >= Duration::from_secs(1));
+    }
+
+    const FORBIDDEN_SCENARIO: &str = r#"
+        {
+            "forbidden": ["panic"],
+            "commands": [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "Hello" },
+                        "expect_prefix": "Hello",
+                        "expect_exact": "Hello World!",
+                        "timeout": 5
+                    }
+                }
+            ]
+        }
+        "#;
+
+    #[test]
+    fn thread_scenario_forbidden_mismatch() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(FORBIDDEN_SCENARIO);
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(
+            unit_channel,
+            &[&[b"thread panicked at main.rs"]],
+            Duration::ZERO,
+        );
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        let command_result = &scenario_result.commands[0];
+        assert_eq!(command_result.verdict, Verdict::Mismatch);
+        assert_eq!(
+            command_result.received,
+            Some(Payload::from(b"thread panicked at main.rs".as_slice()))
+        );
+        assert_eq!(
+            command_result.error.as_deref(),
+            Some("Received a response matching forbidden pattern \"panic\"")
+        );
+    }
+
+    #[test]
+    fn thread_scenario_forbidden_after_last_command_mismatch() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(FORBIDDEN_SCENARIO);
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        // Both frames are queued together, the panic is only left over once the response has passed
+        let scenario_result = respond(
+            unit_channel,
+            &[&[b"Hello World!", b"thread panicked at main.rs"]],
+            Duration::ZERO,
+        );
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.commands[0].verdict, Verdict::Pass);
+        assert_eq!(scenario_result.verdict, Verdict::Mismatch);
+        assert_eq!(
+            scenario_result.error.as_deref(),
+            Some(
+                "Received \"thread panicked at main.rs\" outside of any command, matching forbidden pattern \"panic\""
+            )
+        );
+    }
+
+    #[test]
+    fn thread_command_hex_expectation_mismatch() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(
+            r#"
+            [
+                {
+                    "command": {
+                        "destination": "Connection",
+                        "send": { "type": "Text", "data": "Hello" },
+                        "expect_prefix": { "type": "Hex", "data": "AA55" },
+                        "expect_exact": { "type": "Hex", "data": "AA 55 ?? ?? 01", "mask": "ff ff ff 0f ff" },
+                        "timeout": 5
+                    }
+                }
+            ]
+            "#,
+        );
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(
+            unit_channel,
+            &[&[
+                &[0xaa, 0x55, 0x00, 0x00, 0x02],
+                &[0xaa, 0x55, 0x17, 0xf3, 0x01],
+            ]],
+            Duration::ZERO,
+        );
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Mismatch);
+        let command_result = &scenario_result.commands[0];
+        assert_eq!(
+            command_result.expected,
+            Some(Payload::from(
+                &MaskedBytes::parse_hex("AA55????01").expect("Failed to parse")
+            ))
+        );
+        assert_eq!(
+            command_result.received,
+            Some(Payload::from([0xaa, 0x55, 0x00, 0x00, 0x02].as_slice()))
+        );
+    }
+
+    const JSON_SCENARIO: &str = r#"
+        [
+            {
+                "command": {
+                    "destination": "Connection",
+                    "send": { "type": "Text", "data": "Hello" },
+                    "expect_json": {
+                        "mode": { "equals": "idle" },
+                        "temp": { "type": "number", "gte": 10, "lt": 40 },
+                        "error": { "present": false }
+                    },
+                    "timeout": 5
+                }
+            }
+        ]
+        "#;
+
+    #[test]
+    fn thread_command_json_pass() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(JSON_SCENARIO);
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(
+            unit_channel,
+            &[&[b"Booting...", br#"{ "temp": 21.5, "mode": "idle" }"#]],
+            Duration::ZERO,
+        );
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Pass);
+        assert_eq!(scenario_result.commands[0].error, None);
+    }
+
+    #[test]
+    fn thread_command_json_mismatch() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(JSON_SCENARIO);
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(
+            unit_channel,
+            &[&[br#"{"mode":"busy","temp":55,"error":"overheat"}"#]],
+            Duration::ZERO,
+        );
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Mismatch);
+        let command_result = &scenario_result.commands[0];
+        assert_eq!(
+            command_result.expected,
+            Some(Payload::from(
+                b"error absent; mode == \"idle\"; temp is a number, >= 10, < 40".as_slice()
+            ))
+        );
+        assert_eq!(
+            command_result.error.as_deref(),
+            Some(
+                "error: expected to be absent, got \"overheat\"; mode: expected \"idle\", got \"busy\"; temp: expected less than 40, got 55"
+            )
+        );
+    }
+
+    const MEASURE_SCENARIO: &str = r#"
+        [
+            {
+                "command": {
+                    "destination": "Connection",
+                    "send": { "type": "Text", "data": "Hello" },
+                    "expect_regex": "^V=(?<volts>[0-9.]+) R=",
+                    "measure": {
+                        "battery": { "group": "volts", "min": 3.2, "max": 3.4 },
+                        "rssi": { "offset": 9, "width": 1, "signed": true, "nominal": -60, "tolerance": 5 }
+                    },
+                    "timeout": 5
+                }
+            }
+        ]
+        "#;
+
+    #[test]
+    fn thread_command_measure_pass() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(MEASURE_SCENARIO);
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(unit_channel, &[&[b"V=3.30 R=\xc6"]], Duration::ZERO);
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Pass);
+        assert_eq!(
+            scenario_result.commands[0].measurements,
+            vec![
+                MeasurementResult {
+                    name: String::from("battery"),
+                    value: 3.3,
+                    min: Some(3.2),
+                    max: Some(3.4),
+                    verdict: Verdict::Pass,
+                },
+                MeasurementResult {
+                    name: String::from("rssi"),
+                    value: -58.0,
+                    min: Some(-65.0),
+                    max: Some(-55.0),
+                    verdict: Verdict::Pass,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn thread_command_measure_mismatch() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(MEASURE_SCENARIO);
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(unit_channel, &[&[b"V=3.05 R=\xc6"]], Duration::ZERO);
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Mismatch);
+        let command_result = &scenario_result.commands[0];
+        assert_eq!(
+            command_result.error.as_deref(),
+            Some("battery measured 3.05, expected between 3.2 and 3.4")
+        );
+        assert_eq!(
+            command_result
+                .measurements
+                .iter()
+                .map(|measurement| (measurement.value, measurement.verdict))
+                .collect::<Vec<_>>(),
+            vec![(3.05, Verdict::Mismatch), (-58.0, Verdict::Pass)]
+        );
+    }
+
+    const RETRY_SCENARIO: &str = r#"
+        [
+            {
+                "command": {
+                    "destination": "Connection",
+                    "send": { "type": "Text", "data": "STATE?" },
+                    "expect_prefix": "STATE",
+                    "expect_exact": "STATE READY",
+                    "timeout": "200ms",
+                    "retry_delay": "10ms",
+                    "retries": 2
+                }
+            }
+        ]
+        "#;
+
+    #[test]
+    fn thread_command_retries_pass() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(RETRY_SCENARIO);
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(unit_channel, &[&[], &[b"STATE READY"]], Duration::ZERO);
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Pass);
+        let attempts = &scenario_result.commands[0].attempts;
+        assert_eq!(
+            attempts
+                .iter()
+                .map(|attempt| attempt.verdict)
+                .collect::<Vec<_>>(),
+            vec![Verdict::Timeout, Verdict::Pass]
+        );
+        assert_eq!(
+            attempts[1].received,
+            Some(Payload::from(b"STATE READY".as_slice()))
+        );
+    }
+
+    #[test]
+    fn thread_command_retries_timeout() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file =
+            scenario_file(&RETRY_SCENARIO.replace(r#""retries": 2"#, r#""retries": 1"#));
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        // A wrong answer isn't retried, only a missing one
+        let scenario_result = respond(
+            unit_channel,
+            &[&[], &[b"STATE BUSY"], &[b"STATE READY"]],
+            Duration::ZERO,
+        );
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Mismatch);
+        assert_eq!(scenario_result.commands[0].attempts.len(), 2);
+    }
+
+    #[test]
+    fn thread_command_repeat_until_pass() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file =
+            scenario_file(&RETRY_SCENARIO.replace(r#""retries": 2"#, r#""repeat_until": "5s""#));
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(
+            unit_channel,
+            &[&[b"STATE BUSY"], &[], &[b"STATE READY"]],
+            Duration::ZERO,
+        );
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Pass);
+        assert_eq!(
+            scenario_result.commands[0]
+                .attempts
+                .iter()
+                .map(|attempt| attempt.verdict)
+                .collect::<Vec<_>>(),
+            vec![Verdict::Mismatch, Verdict::Timeout, Verdict::Pass]
+        );
+    }
+
+    #[test]
+    fn thread_command_repeat_until_deadline() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file =
+            scenario_file(&RETRY_SCENARIO.replace(r#""retries": 2"#, r#""repeat_until": "500ms""#));
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(unit_channel, &[], Duration::ZERO);
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Timeout);
+        let command_result = &scenario_result.commands[0];
+        assert!(
+            command_result.attempts.len() >= 2,
+            "Only made {} attempts",
+            command_result.attempts.len()
+        );
+        assert!(
+            command_result.elapsed < Duration::from_millis(700),
+            "Polled past the deadline for {:?}",
+            command_result.elapsed
+        );
+    }
+
+    #[test]
+    fn thread_command_latency_pass() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(
+            r#"[{ "command": { "destination": "Connection", "send": { "type": "Text", "data": "Hello" }, "expect_regex": "^Hello", "min_latency": "10ms", "max_latency": "50ms", "timeout": 5 } }]"#,
+        );
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(
+            unit_channel,
+            &[&[b"Hello World!"]],
+            Duration::from_millis(30),
+        );
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        assert_eq!(scenario_result.verdict, Verdict::Pass);
+        assert_eq!(
+            scenario_result.commands[0].latency,
+            Some(Duration::from_millis(30))
+        );
+    }
+
+    #[test]
+    fn thread_command_latency_mismatch() {
+        let (unit_channel, thread_channel) = setup();
+        let temp_file = scenario_file(
+            r#"[{ "command": { "destination": "Connection", "send": { "type": "Text", "data": "Hello" }, "expect_regex": "^Hello", "max_latency": "50ms", "timeout": 5 } }]"#,
+        );
+        let scenarios = vec![temp_file.path().to_path_buf()];
+
+        let handle = thread::spawn(move || {
+            thread(
+                scenarios,
+                Definitions::new(),
+                Repeat::Times(1),
+                thread_channel,
+            )
+        });
+        let scenario_result = respond(
+            unit_channel,
+            &[&[b"Hello World!"]],
+            Duration::from_millis(80),
+        );
+
+        assert!(handle.join().is_ok(), "Thread joined with fail");
+        let command_result = &scenario_result.commands[0];
+        assert_eq!(command_result.verdict, Verdict::Mismatch);
+        assert_eq!(command_result.latency, Some(Duration::from_millis(80)));
+        assert_eq!(
+            command_result.error.as_deref(),
+            Some("Response took 80ms, more than the max_latency of 50ms")
+        );
+    }
 }
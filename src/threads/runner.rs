@@ -1,11 +1,22 @@
 use crate::connection::Communicate;
+use crate::connection::framing::Framer;
 use crate::threads::controller::{ItcManager, Message};
 use chrono::Local;
 use log::{error, info, trace, warn};
+use std::io::{self, ErrorKind};
+use std::time::Instant;
 
-pub fn thread(connection_handle: &mut Box<dyn Communicate + Send + 'static>, manager: ItcManager) {
+// Big enough for any UDP datagram, so raw framing never splits one
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+pub fn thread(
+    connection_handle: &mut Box<dyn Communicate + Send + 'static>,
+    mut framer: Box<dyn Framer + Send>,
+    manager: ItcManager,
+) {
     info!("Starting Command Runner Thread!");
 
+    let mut buf = vec![0; READ_CHUNK_SIZE];
     'main: loop {
         if let Ok(messages) = manager.try_receive_all() {
             for message in messages {
@@ -13,9 +24,19 @@ pub fn thread(connection_handle: &mut Box<dyn Communicate + Send + 'static>, man
                     Message::StopRunning => break 'main,
                     Message::RunnerSendData { data } => {
                         trace!("Sending data on connection");
-                        if connection_handle.write(&data).is_err() {
-                            error!("Failed to send bytes");
-                            let _ = manager.send(Message::SendError);
+                        let encoded = framer.encode(&data);
+                        // Connections are free to take only part of a frame per write
+                        match connection_handle.write_all(&encoded) {
+                            Ok(()) => {
+                                let _ = manager.send(Message::RunnerSentData {
+                                    timestamp: Local::now(),
+                                    data: encoded,
+                                });
+                            }
+                            Err(..) => {
+                                error!("Failed to send bytes");
+                                let _ = manager.send(Message::SendError);
+                            }
                         }
                     }
                     _ => {
@@ -25,31 +46,60 @@ pub fn thread(connection_handle: &mut Box<dyn Communicate + Send + 'static>, man
             }
         }
 
-        let mut buf: [u8; 256] = [0; 256];
-        if let Ok(bytes_read) = connection_handle.read_until(&mut buf, b'\n') {
-            let mut data = Vec::from(buf);
-            data.truncate(bytes_read - 1);
+        let frames = match connection_handle.read(&mut buf) {
+            Ok(0) if connection_handle.is_datagram() => {
+                trace!("Ignoring an empty datagram");
+                framer.idle(Instant::now())
+            }
+            Ok(0) => {
+                error!("Connection was closed by the other side");
+                let _ = manager.send(Message::ReceiveError);
+                Vec::new()
+            }
+            Ok(bytes_read) => framer.push(&buf[..bytes_read], Instant::now()),
+            // Connections with a read timeout just had nothing to say this time around
+            Err(msg) if is_timeout(&msg) => framer.idle(Instant::now()),
+            Err(..) => {
+                error!("Failed to receive bytes");
+                let _ = manager.send(Message::ReceiveError);
+                Vec::new()
+            }
+        };
+        for data in frames {
             let data_length = data.len();
             let _ = manager.send(Message::RunnerReceivedData {
                 timestamp: Local::now(),
                 data,
                 data_length,
             });
-        } else {
-            error!("Failed to receive bytes");
-            let _ = manager.send(Message::ReceiveError);
+        }
+        for data in framer.drain_rejected() {
+            let _ = manager.send(Message::RunnerRejectedData {
+                timestamp: Local::now(),
+                data,
+            });
+        }
+
+        for (timestamp, data) in connection_handle.drain_stderr() {
+            let _ = manager.send(Message::RunnerStderrData { timestamp, data });
         }
     }
     info!("Command Runner thread has stopped!");
 }
 
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(error.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::connection::Communicate;
+    use crate::connection::framing::{Delimiter, Slip};
     use crossbeam::channel;
     use pretty_assertions::assert_eq;
     use std::io::{Error, Read, Write};
+    use std::sync::{Arc, Mutex};
     use std::thread;
     use std::time::Duration;
 
@@ -59,6 +109,16 @@ mod tests {
         read_index: usize,
     }
 
+    // Hands out one datagram per read, then times out like a quiet UDP socket
+    struct DatagramMockConnection {
+        datagrams: Vec<Vec<u8>>,
+    }
+
+    // Takes a single byte per write, like a connection with a full send buffer
+    struct TrickleMockConnection {
+        message_written: Arc<Mutex<Vec<u8>>>,
+    }
+
     struct FailedReadMockConnection;
     struct FailedWriteMockConnection {
         message_read: Vec<u8>,
@@ -83,7 +143,7 @@ mod tests {
             for byte in buf {
                 self.message_written.push(*byte);
             }
-            Ok(self.message_written.len())
+            Ok(buf.len())
         }
 
         fn flush(&mut self) -> std::io::Result<()> {
@@ -94,6 +154,62 @@ mod tests {
 
     impl Communicate for MockConnection {}
 
+    impl Read for DatagramMockConnection {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.datagrams.is_empty() {
+                thread::sleep(Duration::from_millis(10));
+                return Err(Error::from(ErrorKind::WouldBlock));
+            }
+            let datagram = self.datagrams.remove(0);
+            buf[..datagram.len()].copy_from_slice(&datagram);
+            Ok(datagram.len())
+        }
+    }
+
+    impl Write for DatagramMockConnection {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Communicate for DatagramMockConnection {
+        fn is_datagram(&self) -> bool {
+            true
+        }
+    }
+
+    impl Read for TrickleMockConnection {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            thread::sleep(Duration::from_millis(10));
+            Err(Error::from(ErrorKind::WouldBlock))
+        }
+    }
+
+    impl Write for TrickleMockConnection {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            match buf.first() {
+                Some(byte) => {
+                    self.message_written
+                        .lock()
+                        .expect("Failed to lock written bytes")
+                        .push(*byte);
+                    Ok(1)
+                }
+                None => Ok(0),
+            }
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Communicate for TrickleMockConnection {}
+
     impl Read for FailedReadMockConnection {
         fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
             Err(Error::other("Simulated read failure"))
@@ -137,6 +253,10 @@ mod tests {
 
     impl Communicate for FailedWriteMockConnection {}
 
+    fn lines() -> Box<dyn Framer + Send> {
+        Box::new(Delimiter::new(Vec::from(b"\n")))
+    }
+
     fn setup() -> (MockConnection, ItcManager, ItcManager) {
         let (test_tx, test_rx) = channel::unbounded();
         let (thread_tx, thread_rx) = channel::unbounded();
@@ -183,7 +303,7 @@ mod tests {
             .extend_from_slice(read_string.as_bytes());
         let mut mock_connection: Box<dyn Communicate + Send + 'static> = Box::new(mock_connection);
 
-        let handle = thread::spawn(move || thread(&mut mock_connection, thread_channel));
+        let handle = thread::spawn(move || thread(&mut mock_connection, lines(), thread_channel));
         unit_channel
             .send(Message::StopRunning)
             .expect("Failed to send stop running message");
@@ -200,7 +320,7 @@ mod tests {
             .extend_from_slice(read_string.as_bytes());
         let mut mock_connection: Box<dyn Communicate + Send + 'static> = Box::new(mock_connection);
 
-        let handle = thread::spawn(move || thread(&mut mock_connection, thread_channel));
+        let handle = thread::spawn(move || thread(&mut mock_connection, lines(), thread_channel));
 
         //Should receive something back way faster than 60 seconds
         let received_message = unit_channel
@@ -230,7 +350,7 @@ mod tests {
             .extend_from_slice(read_string.as_bytes());
         let mut mock_connection: Box<dyn Communicate + Send + 'static> = Box::new(mock_connection);
 
-        let handle = thread::spawn(move || thread(&mut mock_connection, thread_channel));
+        let handle = thread::spawn(move || thread(&mut mock_connection, lines(), thread_channel));
         unit_channel
             .send(Message::RunnerSendData {
                 data: Vec::from("Hello World!"),
@@ -243,11 +363,117 @@ mod tests {
         assert!(handle.join().is_ok(), "Thread stopped with error thread")
     }
 
+    #[test]
+    fn thread_data_send_reports_write_time() {
+        let (mut mock_connection, unit_channel, thread_channel) = setup();
+        mock_connection.message_read.extend_from_slice(b"noise\n");
+        let mut mock_connection: Box<dyn Communicate + Send + 'static> = Box::new(mock_connection);
+
+        // SLIP wraps what's sent, so the bytes reported back differ from the ones queued
+        let handle = thread::spawn(move || {
+            thread(
+                &mut mock_connection,
+                Box::new(Slip::new(true)),
+                thread_channel,
+            )
+        });
+        let queued = Local::now();
+        unit_channel
+            .send(Message::RunnerSendData {
+                data: Vec::from("Hello World!"),
+            })
+            .expect("Failed to send send data message");
+        let written = loop {
+            match unit_channel
+                .receive_timeout(Duration::from_secs(5))
+                .expect("Never heard back about the write")
+            {
+                Message::RunnerSentData { timestamp, data } => {
+                    assert_eq!(data, Slip::new(true).encode(b"Hello World!"));
+                    assert_ne!(data, b"Hello World!");
+                    break timestamp;
+                }
+                _ => continue,
+            }
+        };
+        unit_channel
+            .send(Message::StopRunning)
+            .expect("Failed to send stop running message");
+
+        assert!(written >= queued);
+        assert!(handle.join().is_ok(), "Thread stopped with error thread")
+    }
+
+    #[test]
+    fn thread_data_send_partial_writes_pass() {
+        let (_, unit_channel, thread_channel) = setup();
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut mock_connection: Box<dyn Communicate + Send + 'static> =
+            Box::new(TrickleMockConnection {
+                message_written: Arc::clone(&written),
+            });
+
+        let handle = thread::spawn(move || {
+            thread(
+                &mut mock_connection,
+                Box::new(Slip::new(true)),
+                thread_channel,
+            )
+        });
+        unit_channel
+            .send(Message::RunnerSendData {
+                data: Vec::from("Hello World!"),
+            })
+            .expect("Failed to send send data message");
+        match unit_channel
+            .receive_timeout(Duration::from_secs(5))
+            .expect("Never heard back about the write")
+        {
+            Message::RunnerSentData { data, .. } => {
+                assert_eq!(data, Slip::new(true).encode(b"Hello World!"))
+            }
+            other => panic!("Received {other:?} instead of the sent frame"),
+        }
+        unit_channel
+            .send(Message::StopRunning)
+            .expect("Failed to send stop running message");
+
+        assert!(handle.join().is_ok(), "Thread stopped with error thread");
+        assert_eq!(
+            *written.lock().expect("Failed to lock written bytes"),
+            Slip::new(true).encode(b"Hello World!")
+        );
+    }
+
+    #[test]
+    fn thread_data_receive_empty_datagram_pass() {
+        let (_, unit_channel, thread_channel) = setup();
+        let mut mock_connection: Box<dyn Communicate + Send + 'static> =
+            Box::new(DatagramMockConnection {
+                datagrams: vec![Vec::new(), Vec::from("Hello World!\n")],
+            });
+
+        let handle = thread::spawn(move || thread(&mut mock_connection, lines(), thread_channel));
+
+        // An empty datagram used to be taken for the connection closing
+        match unit_channel
+            .receive_timeout(Duration::from_secs(5))
+            .expect("Somehow didn't receive anything back")
+        {
+            Message::RunnerReceivedData { data, .. } => assert_eq!(data, b"Hello World!"),
+            other => panic!("Received {other:?} instead of the datagram after the empty one"),
+        }
+        unit_channel
+            .send(Message::StopRunning)
+            .expect("Failed to send stop running message");
+        assert!(handle.join().is_ok(), "Thread stopped with error thread")
+    }
+
     #[test]
     fn thread_data_receive_fail() {
         let (mock_connection, unit_channel, thread_channel) = fail_read_setup();
         let mut mock_connection: Box<dyn Communicate + Send + 'static> = Box::new(mock_connection);
-        let handle = thread::spawn(move || thread(&mut mock_connection, thread_channel));
+        let handle = thread::spawn(move || thread(&mut mock_connection, lines(), thread_channel));
 
         let received_message = unit_channel
             .receive_timeout(Duration::from_secs(10))
@@ -278,7 +504,7 @@ mod tests {
             })
             .expect("Failed to send send data message");
         let mut mock_connection: Box<dyn Communicate + Send + 'static> = Box::new(mock_connection);
-        let handle = thread::spawn(move || thread(&mut mock_connection, thread_channel));
+        let handle = thread::spawn(move || thread(&mut mock_connection, lines(), thread_channel));
 
         let received_message = unit_channel
             .receive_timeout(Duration::from_secs(10))
@@ -294,6 +520,13 @@ mod tests {
         assert!(handle.join().is_ok(), "Thread stopped with error")
     }
 
+    #[test]
+    fn is_timeout_pass() {
+        assert!(is_timeout(&Error::from(ErrorKind::TimedOut)));
+        assert!(is_timeout(&Error::from(ErrorKind::WouldBlock)));
+        assert!(!is_timeout(&Error::other("Simulated read failure")));
+    }
+
     #[test]
     fn thread_data_send_unhandled_message() {
         let (mut mock_connection, unit_channel, thread_channel) = setup();
@@ -303,7 +536,7 @@ mod tests {
             .extend_from_slice(read_string.as_bytes());
         let mut mock_connection: Box<dyn Communicate + Send + 'static> = Box::new(mock_connection);
 
-        let handle = thread::spawn(move || thread(&mut mock_connection, thread_channel));
+        let handle = thread::spawn(move || thread(&mut mock_connection, lines(), thread_channel));
         unit_channel
             .send(Message::SendError)
             .expect("Failed to send unhandled message");